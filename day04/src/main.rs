@@ -1,4 +1,7 @@
-use std::fs;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+};
 
 #[derive(Debug)]
 pub struct CharArray {
@@ -16,10 +19,10 @@ where
     h: usize,
 }
 
-fn matched<'a, T: 'a, I>(fst: I, snd: I) -> bool
+fn matched<'a, T, I>(fst: I, snd: I) -> bool
 where
     I: IntoIterator<Item = &'a T>,
-    T: PartialEq,
+    T: PartialEq + 'a,
 {
     fst.into_iter().zip(snd).all(|(p, c)| p == c)
 }
@@ -34,32 +37,34 @@ where
             .any(|pat| matched(self.data.iter(), pat.iter()))
     }
 
+    fn diagonal(&self) -> Vec<T> {
+        (0..self.w.min(self.h))
+            .map(|i| self.data[i * self.w + i].clone())
+            .collect()
+    }
+
+    fn anti_diagonal(&self) -> Vec<T> {
+        (0..self.w.min(self.h))
+            .map(|i| self.data[i * self.w + (self.w - 1 - i)].clone())
+            .collect()
+    }
+
+    // A diagonal shorter than the pattern cannot match it, even if its prefix does.
     fn diag_matches(&self, patterns: &[&[T]]) -> u32 {
-        assert!(self.h == self.w);
-        let diag: Vec<T> = self
-            .data
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| i % self.h == i / self.h)
-            .map(|(_, c)| c.clone())
-            .collect();
-        let diag_match = patterns.iter().any(|pat| matched(diag.iter(), pat.iter())) as u32;
-        let diag: Vec<T> = self
-            .data
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| self.h - 1 - (i % self.h) == i / self.h)
-            .map(|(_, c)| c.clone())
-            .collect();
-        let anti_match = patterns.iter().any(|pat| matched(diag.iter(), pat.iter())) as u32;
-        diag_match + anti_match
+        let matches = |diag: Vec<T>| {
+            patterns
+                .iter()
+                .any(|pat| diag.len() == pat.len() && matched(diag.iter(), pat.iter()))
+        };
+        matches(self.diagonal()) as u32 + matches(self.anti_diagonal()) as u32
     }
 }
 
 pub struct KernelConfig {
     kw: usize,
     kh: usize,
-    stride: usize,
+    stride_x: usize,
+    stride_y: usize,
 }
 
 pub struct KernelIterator<'a, 'b, T>
@@ -69,7 +74,8 @@ where
     data: &'a [T],
     w: usize,
     h: usize,
-    cursor: usize,
+    row: usize,
+    col: usize,
     config: &'b KernelConfig,
 }
 
@@ -80,22 +86,23 @@ where
     type Item = Kernel<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let col = self.cursor % self.w;
         let config = self.config;
-        if col + config.kw > self.w {
-            self.cursor = self.cursor - col + self.h;
+        if config.kw > self.w || config.kh > self.h {
+            return None;
+        }
+        if self.col + config.kw > self.w {
+            self.col = 0;
+            self.row += config.stride_y;
         }
-        let row = self.cursor / self.w;
-        let col = self.cursor % self.w;
-        if row + config.kh > self.h || config.kw > self.w {
+        if self.row + config.kh > self.h {
             return None;
         }
         let mut kernel_buf = Vec::with_capacity(config.kw * config.kh);
-        for ki in row..(row + config.kh) {
-            let start = ki * self.w + col;
+        for ki in self.row..(self.row + config.kh) {
+            let start = ki * self.w + self.col;
             kernel_buf.extend_from_slice(&self.data[start..(start + config.kw)]);
         }
-        self.cursor += config.stride;
+        self.col += config.stride_x;
         Some(Kernel {
             data: kernel_buf,
             w: config.kw,
@@ -104,6 +111,114 @@ where
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Direction {
+    const ALL: [Direction; 8] = [
+        Direction::N,
+        Direction::NE,
+        Direction::E,
+        Direction::SE,
+        Direction::S,
+        Direction::SW,
+        Direction::W,
+        Direction::NW,
+    ];
+
+    fn delta(self) -> (i64, i64) {
+        match self {
+            Direction::N => (-1, 0),
+            Direction::NE => (-1, 1),
+            Direction::E => (0, 1),
+            Direction::SE => (1, 1),
+            Direction::S => (1, 0),
+            Direction::SW => (1, -1),
+            Direction::W => (0, -1),
+            Direction::NW => (-1, -1),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WordMatch<'w> {
+    x: usize,
+    y: usize,
+    direction: Direction,
+    word: &'w str,
+}
+
+pub struct WordAutomaton {
+    goto: Vec<HashMap<u8, usize>>,
+    fail: Vec<usize>,
+    output: Vec<Vec<usize>>,
+}
+
+impl WordAutomaton {
+    fn new(words: &[&str]) -> Self {
+        let mut goto = vec![HashMap::new()];
+        let mut output = vec![vec![]];
+        for (word_idx, word) in words.iter().enumerate().filter(|(_, w)| !w.is_empty()) {
+            let mut state = 0;
+            for b in word.bytes() {
+                state = match goto[state].get(&b) {
+                    Some(&next) => next,
+                    None => {
+                        goto.push(HashMap::new());
+                        output.push(vec![]);
+                        let next = goto.len() - 1;
+                        goto[state].insert(b, next);
+                        next
+                    }
+                };
+            }
+            output[state].push(word_idx);
+        }
+        // Breadth-first so that every failure target is resolved before its children.
+        let mut fail = vec![0; goto.len()];
+        let mut queue = goto[0].values().copied().collect::<VecDeque<usize>>();
+        while let Some(state) = queue.pop_front() {
+            let edges = goto[state]
+                .iter()
+                .map(|(&b, &next)| (b, next))
+                .collect::<Vec<(u8, usize)>>();
+            for (b, next) in edges {
+                queue.push_back(next);
+                let mut f = fail[state];
+                while f != 0 && !goto[f].contains_key(&b) {
+                    f = fail[f];
+                }
+                let target = goto[f].get(&b).copied().filter(|&t| t != next).unwrap_or(0);
+                fail[next] = target;
+                let inherited = output[target].clone();
+                output[next].extend(inherited);
+            }
+        }
+        Self { goto, fail, output }
+    }
+
+    fn step(&self, mut state: usize, b: u8) -> usize {
+        loop {
+            if let Some(&next) = self.goto[state].get(&b) {
+                return next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.fail[state];
+        }
+    }
+}
+
 impl CharArray {
     fn from(raw: &str) -> Self {
         let mut lines = raw.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
@@ -125,11 +240,13 @@ impl CharArray {
     }
 
     fn kernels<'a, 'b>(&'a self, config: &'b KernelConfig) -> KernelIterator<'a, 'b, u8> {
+        assert!(config.stride_x > 0 && config.stride_y > 0);
         KernelIterator {
             data: &self.contents,
             w: self.width,
             h: self.height,
-            cursor: 0,
+            row: 0,
+            col: 0,
             config,
         }
     }
@@ -143,7 +260,8 @@ impl CharArray {
             .kernels(&KernelConfig {
                 kw: pat_len,
                 kh: 1,
-                stride: 1,
+                stride_x: 1,
+                stride_y: 1,
             })
             .filter(|k| k.matches(&patterns))
             .count() as u32;
@@ -151,7 +269,8 @@ impl CharArray {
             .kernels(&KernelConfig {
                 kw: 1,
                 kh: pat_len,
-                stride: 1,
+                stride_x: 1,
+                stride_y: 1,
             })
             .filter(|k| k.matches(&patterns))
             .count() as u32;
@@ -159,13 +278,71 @@ impl CharArray {
             .kernels(&KernelConfig {
                 kw: pat_len,
                 kh: pat_len,
-                stride: 1,
+                stride_x: 1,
+                stride_y: 1,
             })
             .map(|k| k.diag_matches(&patterns))
             .sum::<u32>();
         matches
     }
 
+    fn contains(&self, y: i64, x: i64) -> bool {
+        y >= 0 && x >= 0 && (y as usize) < self.height && (x as usize) < self.width
+    }
+
+    fn find_words<'w>(&self, words: &[&'w str]) -> Vec<WordMatch<'w>> {
+        let automaton = WordAutomaton::new(words);
+        let mut matches = vec![];
+        for direction in Direction::ALL {
+            let (dy, dx) = direction.delta();
+            for y in 0..self.height as i64 {
+                for x in 0..self.width as i64 {
+                    // Only start from cells that begin a line in this direction.
+                    if self.contains(y - dy, x - dx) {
+                        continue;
+                    }
+                    let mut state = 0;
+                    let (mut cy, mut cx) = (y, x);
+                    while self.contains(cy, cx) {
+                        state = automaton.step(state, self[cy as usize * self.width + cx as usize]);
+                        for &word_idx in &automaton.output[state] {
+                            let back = words[word_idx].len() as i64 - 1;
+                            matches.push(WordMatch {
+                                x: (cx - back * dx) as usize,
+                                y: (cy - back * dy) as usize,
+                                direction,
+                                word: words[word_idx],
+                            });
+                        }
+                        cy += dy;
+                        cx += dx;
+                    }
+                }
+            }
+        }
+        matches
+    }
+
+    fn render_matches(&self, matches: &[WordMatch]) -> String {
+        let mut keep = vec![false; self.contents.len()];
+        for m in matches {
+            let (dy, dx) = m.direction.delta();
+            for k in 0..m.word.len() as i64 {
+                let y = m.y as i64 + k * dy;
+                let x = m.x as i64 + k * dx;
+                keep[y as usize * self.width + x as usize] = true;
+            }
+        }
+        let mut rendered = String::with_capacity((self.width + 1) * self.height);
+        for (cursor, &c) in self.contents.iter().enumerate() {
+            rendered.push(if keep[cursor] { c as char } else { '.' });
+            if (cursor + 1) % self.width == 0 {
+                rendered.push('\n');
+            }
+        }
+        rendered
+    }
+
     fn count_part2(&self, pattern: &str) -> u32 {
         let pat_len = pattern.len();
         let rev = pattern.bytes().rev().collect::<Vec<u8>>();
@@ -174,7 +351,8 @@ impl CharArray {
         self.kernels(&KernelConfig {
             kw: pat_len,
             kh: pat_len,
-            stride: 1,
+            stride_x: 1,
+            stride_y: 1,
         })
         .map(|k| k.diag_matches(&patterns))
         .filter(|&m| m == 2)
@@ -211,4 +389,20 @@ fn main() {
     println!("Day 5, part 1: {result_part1}");
     let result_part2 = processed.count_part2("MAS");
     println!("Day 5, part 1: {result_part2}");
+
+    let words = std::env::args().skip(1).collect::<Vec<String>>();
+    if !words.is_empty() {
+        let words = words.iter().map(|w| w.as_str()).collect::<Vec<&str>>();
+        let matches = processed.find_words(&words);
+        for m in &matches {
+            println!(
+                "{word} at ({x}, {y}) going {direction:?}",
+                word = m.word,
+                x = m.x,
+                y = m.y,
+                direction = m.direction
+            );
+        }
+        print!("{}", processed.render_matches(&matches));
+    }
 }
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+};
 
 #[derive(Debug)]
 pub struct Pages {
@@ -6,36 +9,336 @@ pub struct Pages {
     updates: Vec<Vec<u32>>,
 }
 
+pub struct SortedUpdate {
+    pages: Vec<u32>,
+    unique: bool,
+}
+
+pub struct Repair {
+    pages: Vec<u32>,
+    moved: Vec<u32>,
+}
+
+fn format_cycle(cycle: &[u32]) -> String {
+    cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(" -> ")
+}
+
 impl Pages {
-    fn valid_invalid(&self) -> (u32, u32) {
+    fn successors(&self, page: u32) -> &[u32] {
+        self.rule_map
+            .get(&page)
+            .map(|s| s.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn sort_update(&self, update: &[u32]) -> Result<SortedUpdate, Vec<u32>> {
+        self.sort_update_with(update, &[])
+    }
+
+    // Kahn's algorithm over the rules restricted to the pages of `update`, plus `extra`
+    // rules given as pairs of positions. Ties are broken by original position, so an
+    // update that is already valid sorts to itself. On a cycle, the pages forming it are
+    // returned in rule order.
+    fn sort_update_with(
+        &self,
+        update: &[u32],
+        extra: &[(usize, usize)],
+    ) -> Result<SortedUpdate, Vec<u32>> {
+        let position: HashMap<u32, usize> =
+            update.iter().enumerate().map(|(i, &p)| (p, i)).collect();
+        let mut succs = vec![vec![]; update.len()];
+        let mut preds = vec![vec![]; update.len()];
+        let mut in_degree = vec![0; update.len()];
+        let rules = update.iter().enumerate().flat_map(|(i, &page)| {
+            self.successors(page)
+                .iter()
+                .filter_map(|succ| position.get(succ))
+                .map(move |&j| (i, j))
+        });
+        for (i, j) in rules.chain(extra.iter().copied()) {
+            succs[i].push(j);
+            preds[j].push(i);
+            in_degree[j] += 1;
+        }
+        let mut ready: BTreeSet<usize> = (0..update.len()).filter(|&i| in_degree[i] == 0).collect();
+        let mut pages = Vec::with_capacity(update.len());
+        let mut unique = true;
+        while let Some(i) = ready.pop_first() {
+            if !ready.is_empty() {
+                unique = false;
+            }
+            pages.push(update[i]);
+            for &j in &succs[i] {
+                in_degree[j] -= 1;
+                if in_degree[j] == 0 {
+                    ready.insert(j);
+                }
+            }
+        }
+        if pages.len() == update.len() {
+            return Ok(SortedUpdate { pages, unique });
+        }
+        // Every page left over still has a left-over predecessor, so walking predecessors
+        // must eventually revisit a page.
+        let mut cursor = (0..update.len())
+            .find(|&i| in_degree[i] > 0)
+            .expect("Unsorted pages should remain");
+        let mut walk = vec![];
+        let mut seen = HashMap::new();
+        while !seen.contains_key(&cursor) {
+            seen.insert(cursor, walk.len());
+            walk.push(cursor);
+            cursor = *preds[cursor]
+                .iter()
+                .find(|&&p| in_degree[p] > 0)
+                .expect("Left-over page should have a left-over predecessor");
+        }
+        let mut cycle: Vec<u32> = walk[seen[&cursor]..].iter().map(|&i| update[i]).collect();
+        cycle.reverse();
+        Err(cycle)
+    }
+
+    fn valid_invalid(&self) -> Result<(u32, u32), ()> {
         let mut valid_sum = 0;
         let mut invalid_sum = 0;
-        for update in &self.updates {
-            let mut visited = vec![];
-            let mut invalid = false;
-            for item in update {
-                let dependencies = self.rule_map.get(item).expect("Page should be in map");
-                let mut tail_idx = visited.len();
-                visited.push(item);
-                if let Some((idx, _vf)) = visited
-                    .iter()
-                    .enumerate()
-                    .find(|&(_, v)| dependencies.contains(v))
-                {
-                    while tail_idx > idx {
-                        visited.swap(tail_idx, tail_idx - 1);
-                        tail_idx -= 1;
+        for (idx, update) in self.updates.iter().enumerate() {
+            let sorted = self.sort_update(update).map_err(|cycle| {
+                eprintln!(
+                    "ERROR: Rules for update {row} contain a cycle: {cycle}.",
+                    row = idx + 1,
+                    cycle = format_cycle(&cycle)
+                );
+            })?;
+            if sorted.pages == *update {
+                valid_sum += update[update.len() / 2];
+            } else {
+                if !sorted.unique {
+                    eprintln!(
+                        "WARNING: Update {row} has several valid orders, middle page {mid} is ambiguous.",
+                        row = idx + 1,
+                        mid = sorted.pages[sorted.pages.len() / 2]
+                    );
+                }
+                invalid_sum += sorted.pages[sorted.pages.len() / 2];
+            }
+        }
+        Ok((valid_sum, invalid_sum))
+    }
+    // Pages reachable from `from` by following rules, only through pages of `scope` if
+    // given. The full rule set is usually cyclic, so unscoped queries often reach both ways.
+    fn reachable(&self, from: u32, scope: Option<&HashSet<u32>>) -> HashSet<u32> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(page) = stack.pop() {
+            for &succ in self.successors(page) {
+                if scope.is_some_and(|s| !s.contains(&succ)) {
+                    continue;
+                }
+                if seen.insert(succ) {
+                    stack.push(succ);
+                }
+            }
+        }
+        seen
+    }
+
+    // Errors when `a` and `b` reach each other, as neither order is then forced.
+    fn must_precede(&self, a: u32, b: u32, scope: Option<&[u32]>) -> Result<bool, ()> {
+        let scope = scope.map(|s| s.iter().copied().collect::<HashSet<u32>>());
+        let forward = self.reachable(a, scope.as_ref()).contains(&b);
+        if forward && self.reachable(b, scope.as_ref()).contains(&a) {
+            eprintln!("ERROR: {a} and {b} reach each other, the rules between them are cyclic.");
+            return Err(());
+        }
+        Ok(forward)
+    }
+
+    fn unconstrained_pairs(&self, update: &[u32]) -> Vec<(u32, u32)> {
+        let scope = update.iter().copied().collect::<HashSet<u32>>();
+        let closure = update
+            .iter()
+            .map(|&p| (p, self.reachable(p, Some(&scope))))
+            .collect::<HashMap<u32, HashSet<u32>>>();
+        let mut pairs = vec![];
+        for (i, &a) in update.iter().enumerate() {
+            for &b in &update[(i + 1)..] {
+                if !closure[&a].contains(&b) && !closure[&b].contains(&a) {
+                    pairs.push((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    // Only explicit rules are reported, not the ones implied through other pages.
+    fn broken_rules(&self, update: &[u32]) -> Vec<(u32, u32)> {
+        let position: HashMap<u32, usize> =
+            update.iter().enumerate().map(|(i, &p)| (p, i)).collect();
+        let mut broken = vec![];
+        for (i, &page) in update.iter().enumerate() {
+            for &succ in self.successors(page) {
+                if position.get(&succ).is_some_and(|&j| j < i) {
+                    broken.push((page, succ));
+                }
+            }
+        }
+        broken.sort();
+        broken
+    }
+
+    // Broken rules that no chain of other broken rules implies. Every broken rule points
+    // backwards in the update, so the broken rules form a DAG and the reduction is unique.
+    fn violated_rules(&self, update: &[u32]) -> Vec<(u32, u32)> {
+        let broken = self.broken_rules(update);
+        let mut succs: HashMap<u32, Vec<u32>> = HashMap::new();
+        for &(a, b) in &broken {
+            succs.entry(a).or_default().push(b);
+        }
+        let implied = |a: u32, b: u32| {
+            let mut seen = HashSet::new();
+            let mut stack = succs[&a]
+                .iter()
+                .copied()
+                .filter(|&s| s != b)
+                .collect::<Vec<u32>>();
+            while let Some(page) = stack.pop() {
+                if page == b {
+                    return true;
+                }
+                if seen.insert(page) {
+                    stack.extend(succs.get(&page).into_iter().flatten());
+                }
+            }
+            false
+        };
+        broken
+            .into_iter()
+            .filter(|&(a, b)| !implied(a, b))
+            .collect()
+    }
+
+    // Largest set of positions whose relative order no rule contradicts, even through
+    // other pages of the update. Position `i` conflicts with a later `j` when `j` must
+    // precede it, which is a partial order, so this is a maximum antichain. By Dilworth
+    // and König it follows from a maximum matching between conflicting pairs: the
+    // positions reachable on the left but not on the right from unmatched left positions
+    // along alternating paths.
+    fn longest_consistent(&self, update: &[u32]) -> Vec<usize> {
+        let scope = update.iter().copied().collect::<HashSet<u32>>();
+        let closure = update
+            .iter()
+            .map(|&p| self.reachable(p, Some(&scope)))
+            .collect::<Vec<HashSet<u32>>>();
+        let n = update.len();
+        let later = (0..n)
+            .map(|i| {
+                ((i + 1)..n)
+                    .filter(|&j| closure[j].contains(&update[i]))
+                    .collect()
+            })
+            .collect::<Vec<Vec<usize>>>();
+
+        // Kuhn's augmenting paths, `matched[j]` being the left partner of right `j`.
+        fn augment(
+            i: usize,
+            later: &[Vec<usize>],
+            seen: &mut [bool],
+            matched: &mut [Option<usize>],
+        ) -> bool {
+            for &j in &later[i] {
+                if seen[j] {
+                    continue;
+                }
+                seen[j] = true;
+                if matched[j].is_none_or(|k| augment(k, later, seen, matched)) {
+                    matched[j] = Some(i);
+                    return true;
+                }
+            }
+            false
+        }
+
+        let mut matched = vec![None; n];
+        let has_partner = (0..n)
+            .map(|i| augment(i, &later, &mut vec![false; n], &mut matched))
+            .collect::<Vec<bool>>();
+        let mut left = vec![false; n];
+        let mut right = vec![false; n];
+        let mut stack = (0..n).filter(|&i| !has_partner[i]).collect::<Vec<usize>>();
+        for &i in &stack {
+            left[i] = true;
+        }
+        while let Some(i) = stack.pop() {
+            for &j in &later[i] {
+                if right[j] {
+                    continue;
+                }
+                right[j] = true;
+                if let Some(k) = matched[j] {
+                    if !left[k] {
+                        left[k] = true;
+                        stack.push(k);
                     }
-                    invalid = true;
                 }
             }
-            if invalid {
-                invalid_sum += visited[visited.len() / 2];
-            } else {
-                valid_sum += update[update.len() / 2];
+        }
+        (0..n).filter(|&i| left[i] && !right[i]).collect()
+    }
+
+    fn repair(&self, update: &[u32]) -> Result<Repair, Vec<u32>> {
+        let kept = self.longest_consistent(update);
+        let chain = kept.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>();
+        let sorted = self.sort_update_with(update, &chain)?;
+        let moved = (0..update.len())
+            .filter(|i| !kept.contains(i))
+            .map(|i| update[i])
+            .collect();
+        Ok(Repair {
+            pages: sorted.pages,
+            moved,
+        })
+    }
+
+    fn to_dot(&self, update: Option<&[u32]>) -> String {
+        let scope = update.map(|u| u.iter().copied().collect::<HashSet<u32>>());
+        let violated = update.map(|u| self.broken_rules(u)).unwrap_or_default();
+        let mut nodes = self
+            .rule_map
+            .keys()
+            .copied()
+            .filter(|p| scope.as_ref().is_none_or(|s| s.contains(p)))
+            .collect::<Vec<u32>>();
+        if let Some(update) = update {
+            nodes.extend(update.iter().filter(|p| !self.rule_map.contains_key(p)));
+        }
+        nodes.sort();
+        let mut dot = String::from("digraph rules {\n");
+        for &page in &nodes {
+            dot.push_str(&format!("    {page};\n"));
+        }
+        for &page in &nodes {
+            let mut succs = self
+                .successors(page)
+                .iter()
+                .copied()
+                .filter(|p| scope.as_ref().is_none_or(|s| s.contains(p)))
+                .collect::<Vec<u32>>();
+            succs.sort();
+            for succ in succs {
+                if violated.contains(&(page, succ)) {
+                    dot.push_str(&format!("    {page} -> {succ} [color=red];\n"));
+                } else {
+                    dot.push_str(&format!("    {page} -> {succ};\n"));
+                }
             }
         }
-        (valid_sum, invalid_sum)
+        dot.push_str("}\n");
+        dot
     }
 }
 
@@ -96,7 +399,80 @@ fn main() {
         .map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))
         .unwrap();
     let pages = parse_pages(&raw).unwrap();
-    let (result_part1, result_part2) = pages.valid_invalid();
+    let (result_part1, result_part2) = pages.valid_invalid().unwrap();
     println!("Day 5, part 1: {result_part1}");
     println!("Day 5, part 2: {result_part2}");
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let args = args.iter().map(|a| a.as_str()).collect::<Vec<&str>>();
+    let update = |arg: &str| -> Option<&[u32]> {
+        let row = arg
+            .parse::<usize>()
+            .ok()
+            .filter(|row| (1..=pages.updates.len()).contains(row));
+        if row.is_none() {
+            eprintln!(
+                "ERROR: Invalid update {arg}, expected 1 to {count}.",
+                count = pages.updates.len()
+            );
+        }
+        row.map(|row| pages.updates[row - 1].as_slice())
+    };
+    match args.as_slice() {
+        [] => {}
+        ["dot"] => print!("{}", pages.to_dot(None)),
+        ["dot", row] => {
+            if let Some(update) = update(row) {
+                print!("{}", pages.to_dot(Some(update)));
+            }
+        }
+        ["precede", a, b] => {
+            let a = a.parse().expect("Page should be an integer");
+            let b = b.parse().expect("Page should be an integer");
+            if let Ok(precedes) = pages.must_precede(a, b, None) {
+                println!("{a} must precede {b}: {precedes}");
+            }
+        }
+        ["precede", a, b, row] => {
+            let a = a.parse().expect("Page should be an integer");
+            let b = b.parse().expect("Page should be an integer");
+            if let Some(Ok(precedes)) =
+                update(row).map(|scope| pages.must_precede(a, b, Some(scope)))
+            {
+                println!("{a} must precede {b}: {precedes}");
+            }
+        }
+        ["unconstrained", row] => {
+            for (a, b) in update(row)
+                .map(|u| pages.unconstrained_pairs(u))
+                .unwrap_or_default()
+            {
+                println!("{a} {b}");
+            }
+        }
+        ["repair"] => {
+            for (idx, update) in pages.updates.iter().enumerate() {
+                let repair = pages.repair(update).unwrap();
+                if repair.moved.is_empty() {
+                    continue;
+                }
+                println!(
+                    "Update {row}: {count} moves {moved:?} -> {pages:?}",
+                    row = idx + 1,
+                    count = repair.moved.len(),
+                    moved = repair.moved,
+                    pages = repair.pages
+                );
+            }
+        }
+        ["violations", row] => {
+            for (a, b) in update(row)
+                .map(|u| pages.violated_rules(u))
+                .unwrap_or_default()
+            {
+                println!("{a}|{b}");
+            }
+        }
+        _ => eprintln!("ERROR: Unknown command: {}", args.join(" ")),
+    }
 }
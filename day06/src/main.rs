@@ -48,6 +48,7 @@ where
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Day6Player {
     y: i32,
     x: i32,
@@ -56,15 +57,186 @@ pub struct Day6Player {
     cursor: u8,
 }
 
-fn find_player(contents: &CharArray, chars: &str) -> Result<Day6Player, ()> {
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    fn insert(&mut self, idx: usize) -> bool {
+        let (word, bit) = (idx / 64, 1 << (idx % 64));
+        let fresh = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        fresh
+    }
+
+    fn remove(&mut self, idx: usize) {
+        self.words[idx / 64] &= !(1 << (idx % 64));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnRule {
+    Left,
+    Right,
+    Reverse,
+}
+
+impl TurnRule {
+    fn from(raw: &str) -> Result<Self, ()> {
+        match raw {
+            "L" | "left" => Ok(TurnRule::Left),
+            "R" | "right" => Ok(TurnRule::Right),
+            "B" | "reverse" => Ok(TurnRule::Reverse),
+            _ => {
+                eprintln!("ERROR: Unknown turn rule {raw}, expected L, R or B.");
+                Err(())
+            }
+        }
+    }
+
+    fn apply(self, dir_y: i32, dir_x: i32) -> (i32, i32) {
+        match self {
+            TurnRule::Left => {
+                let (dir_y, dir_x) = turn_right(dir_y, dir_x);
+                (-dir_y, -dir_x)
+            }
+            TurnRule::Right => turn_right(dir_y, dir_x),
+            TurnRule::Reverse => (-dir_y, -dir_x),
+        }
+    }
+}
+
+pub struct Guard {
+    player: Day6Player,
+    turn: TurnRule,
+}
+
+// Besides `#` obstacles, `N`, `E`, `S` and `W` tiles can only be entered walking in that
+// direction, and each lowercase letter other than the `v` guard marks a pair of
+// teleporters.
+pub struct Tiles {
+    teleports: HashMap<usize, usize>,
+    one_ways: usize,
+}
+
+fn one_way(c: u8) -> Option<(i32, i32)> {
+    match c {
+        b'N' => Some((-1, 0)),
+        b'E' => Some((0, 1)),
+        b'S' => Some((1, 0)),
+        b'W' => Some((0, -1)),
+        _ => None,
+    }
+}
+
+const GUARDS: &str = "><^v";
+
+fn parse_tiles(contents: &CharArray) -> Result<Tiles, ()> {
+    let mut pads: HashMap<u8, Vec<usize>> = HashMap::new();
+    for (cursor, &c) in contents.contents.iter().enumerate() {
+        if c.is_ascii_lowercase() && !GUARDS.contains(c as char) {
+            pads.entry(c).or_default().push(cursor);
+        }
+    }
+    let mut teleports = HashMap::new();
+    for (c, cells) in pads {
+        let &[fst, snd] = cells.as_slice() else {
+            eprintln!(
+                "ERROR: Teleporter {pad} appears {count} times instead of 2.",
+                pad = c as char,
+                count = cells.len()
+            );
+            return Err(());
+        };
+        teleports.insert(fst, snd);
+        teleports.insert(snd, fst);
+    }
+    let one_ways = contents
+        .contents
+        .iter()
+        .filter(|&&c| one_way(c).is_some())
+        .count();
+    Ok(Tiles {
+        teleports,
+        one_ways,
+    })
+}
+
+impl Tiles {
+    fn is_plain(&self) -> bool {
+        self.teleports.is_empty() && self.one_ways == 0
+    }
+}
+
+#[derive(Debug)]
+pub enum WalkOutcome {
+    Exit {
+        visited: Vec<usize>,
+    },
+    Loop {
+        entry: Day6Player,
+        length: u32,
+        visited: Vec<usize>,
+    },
+    Stuck {
+        visited: Vec<usize>,
+    },
+}
+
+enum Move {
+    To {
+        player: Day6Player,
+        via: Option<usize>,
+    },
+    Exit,
+    Stuck,
+}
+
+fn turn_right(dir_y: i32, dir_x: i32) -> (i32, i32) {
+    match (dir_y, dir_x) {
+        (-1, 0) => (0, 1),
+        (0, 1) => (1, 0),
+        (1, 0) => (0, -1),
+        (0, -1) => (-1, 0),
+        _ => unreachable!(),
+    }
+}
+
+fn dir_index(dir_y: i32, dir_x: i32) -> usize {
+    match (dir_y, dir_x) {
+        (0, 1) => 0,
+        (1, 0) => 1,
+        (0, -1) => 2,
+        (-1, 0) => 3,
+        _ => unreachable!(),
+    }
+}
+
+fn dir_cursor(dir_y: i32, dir_x: i32) -> u8 {
+    b"><^v"[match (dir_y, dir_x) {
+        (0, 1) => 0,
+        (0, -1) => 1,
+        (-1, 0) => 2,
+        (1, 0) => 3,
+        _ => unreachable!(),
+    }]
+}
+
+fn find_guards(contents: &CharArray, chars: &str) -> Vec<Day6Player> {
     let mut char_map = HashMap::new();
     let to_match = chars.bytes().collect::<Vec<u8>>();
     for (c, uc) in chars.chars().zip(to_match.iter()) {
         char_map.insert(uc, c);
     }
+    let mut guards = vec![];
     for y in 0..contents.height {
         for x in 0..contents.width {
-            // if chars.itercontains(contents[y * contents.width + x]):
             let current = &contents[y * contents.width + x];
             if let Some(c) = to_match.iter().find(|&c| c == current) {
                 let (dir_y, dir_x) = match char_map.get(c) {
@@ -74,7 +246,7 @@ fn find_player(contents: &CharArray, chars: &str) -> Result<Day6Player, ()> {
                     Some('^') => (-1, 0),
                     _ => unreachable!(),
                 };
-                return Ok(Day6Player {
+                guards.push(Day6Player {
                     y: y as i32,
                     x: x as i32,
                     dir_y,
@@ -84,118 +256,310 @@ fn find_player(contents: &CharArray, chars: &str) -> Result<Day6Player, ()> {
             }
         }
     }
-    eprintln!("ERROR: Could not find player in map");
-    Err(())
+    guards
 }
 
-fn walk_map(contents: &mut CharArray) -> Result<Option<u32>, ()> {
-    let mut player = find_player(contents, "><^v")?;
-    let mut result = 0;
+fn next_move(
+    contents: &CharArray,
+    tiles: &Tiles,
+    player: &Day6Player,
+    turn: TurnRule,
+    obstruction: Option<usize>,
+) -> Move {
     let barrier = "#".bytes().next().expect("ascii");
-    let cursors = "><^v".bytes().collect::<Vec<u8>>();
-    'outer: loop {
-        let cur = (player.y as usize) * contents.width + player.x as usize;
-        if contents[cur] == player.cursor {
-            if result != 0 {
-                return Ok(None);
-            }
-            result += 1;
-        } else if !cursors.contains(&contents[cur]) {
-            contents[cur] = player.cursor;
-            result += 1;
-        }
-        let mut dir_x = player.dir_x;
-        let mut dir_y = player.dir_y;
-        let mut nx = player.x + dir_x;
-        let mut ny = player.y + dir_y;
+    let (mut dir_y, mut dir_x) = (player.dir_y, player.dir_x);
+    for _ in 0..4 {
+        let nx = player.x + dir_x;
+        let ny = player.y + dir_y;
         if nx < 0 || (nx as usize) >= contents.width || ny < 0 || (ny as usize) >= contents.height {
-            break;
-        }
-        let mut tries = 4;
-        while contents[(ny as usize) * contents.width + (nx as usize)] == barrier && tries > 0 {
-            (dir_y, dir_x) = match (dir_y, dir_x) {
-                (-1, 0) => (0, 1),
-                (0, 1) => (1, 0),
-                (1, 0) => (0, -1),
-                (0, -1) => (-1, 0),
-                _ => unreachable!(),
+            return Move::Exit;
+        }
+        let next = (ny as usize) * contents.width + (nx as usize);
+        let blocked = contents[next] == barrier
+            || obstruction == Some(next)
+            || one_way(contents[next]).is_some_and(|dir| dir != (dir_y, dir_x));
+        if !blocked {
+            let (via, next) = match tiles.teleports.get(&next) {
+                Some(&exit) => (Some(next), exit),
+                None => (None, next),
+            };
+            return Move::To {
+                player: Day6Player {
+                    y: (next / contents.width) as i32,
+                    x: (next % contents.width) as i32,
+                    dir_y,
+                    dir_x,
+                    cursor: dir_cursor(dir_y, dir_x),
+                },
+                via,
             };
-            nx = player.x as i32 + dir_x;
-            ny = player.y as i32 + dir_y;
-            if nx < 0
-                || (nx as usize) >= contents.width
-                || ny < 0
-                || (ny as usize) >= contents.height
-            {
-                break 'outer;
+        }
+        (dir_y, dir_x) = turn.apply(dir_y, dir_x);
+    }
+    Move::Stuck
+}
+
+// Walks without touching the map: a loop is a repeated (cell, direction) state on arrival,
+// which fully determines the rest of the walk.
+fn walk_map(
+    contents: &CharArray,
+    tiles: &Tiles,
+    guard: &Guard,
+    obstruction: Option<usize>,
+) -> WalkOutcome {
+    let cells = contents.contents.len();
+    let mut seen_states = BitSet::new(cells * 4);
+    let mut seen_cells = BitSet::new(cells);
+    let mut visited = vec![];
+    let mut player = guard.player.clone();
+    let mut via = None;
+    loop {
+        let cur = (player.y as usize) * contents.width + player.x as usize;
+        for cell in via.into_iter().chain([cur]) {
+            if seen_cells.insert(cell) {
+                visited.push(cell);
             }
-            tries -= 1;
         }
-        if tries == 0 {
-            eprintln!("ERROR: player is stuck.");
-            return Err(());
+        if !seen_states.insert(cur * 4 + dir_index(player.dir_y, player.dir_x)) {
+            let length = loop_length(contents, tiles, &player, guard.turn, obstruction);
+            return WalkOutcome::Loop {
+                entry: player,
+                length,
+                visited,
+            };
         }
-        let cursor = match (dir_y, dir_x) {
-            (0, 1) => ">",
-            (1, 0) => "v",
-            (0, -1) => "<",
-            (-1, 0) => "^",
-            _ => unreachable!(),
+        (player, via) = match next_move(contents, tiles, &player, guard.turn, obstruction) {
+            Move::To { player, via } => (player, via),
+            Move::Exit => return WalkOutcome::Exit { visited },
+            Move::Stuck => return WalkOutcome::Stuck { visited },
+        };
+    }
+}
+
+fn loop_length(
+    contents: &CharArray,
+    tiles: &Tiles,
+    entry: &Day6Player,
+    turn: TurnRule,
+    obstruction: Option<usize>,
+) -> u32 {
+    let mut player = entry.clone();
+    let mut length = 0;
+    loop {
+        let Move::To { player: next, .. } = next_move(contents, tiles, &player, turn, obstruction)
+        else {
+            unreachable!("A loop should keep moving");
+        };
+        length += 1;
+        if next.x == entry.x && next.y == entry.y && next.cursor == entry.cursor {
+            return length;
         }
-        .bytes()
-        .next()
-        .expect("ascii");
-        player.x = nx;
-        player.y = ny;
-        player.dir_y = dir_y;
-        player.dir_x = dir_x;
-        player.cursor = cursor;
+        player = next;
     }
-    Ok(Some(result))
 }
 
-fn reset_map(map: &mut CharArray, start_pos: &Day6Player) {
-    let barrier = "#".bytes().next().expect("ascii");
-    let dot = ".".bytes().next().expect("ascii");
-    for cursor in 0..map.contents.len() {
-        if map[cursor] != barrier {
-            map[cursor] = dot;
+fn render_covered(contents: &CharArray, visited: &[usize]) -> String {
+    let mut covered = vec![false; contents.contents.len()];
+    for &cell in visited {
+        covered[cell] = true;
+    }
+    let mut rendered = String::with_capacity((contents.width + 1) * contents.height);
+    for (cursor, &c) in contents.contents.iter().enumerate() {
+        rendered.push(if covered[cursor] { 'X' } else { c as char });
+        if (cursor + 1) % contents.width == 0 {
+            rendered.push('\n');
         }
     }
-    let cursor = (start_pos.y as usize) * map.width + (start_pos.x as usize);
-    map[cursor] = start_pos.cursor;
+    rendered
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JumpOutcome {
+    Exit,
+    Loop,
+    Stuck,
+}
+
+// For every cell and direction (in `dir_index` order), the cell where a guard walking that
+// way stops in front of an obstacle, or `None` if it walks off the map.
+pub struct JumpTable {
+    width: usize,
+    height: usize,
+    blocked: Vec<bool>,
+    stops: [Vec<Option<usize>>; 4],
+    seen_states: BitSet,
+}
+
+impl JumpTable {
+    fn new(contents: &CharArray) -> Self {
+        let barrier = "#".bytes().next().expect("ascii");
+        let cells = contents.contents.len();
+        let mut table = Self {
+            width: contents.width,
+            height: contents.height,
+            blocked: contents.contents.iter().map(|&c| c == barrier).collect(),
+            stops: std::array::from_fn(|_| vec![None; cells]),
+            seen_states: BitSet::new(cells * 4),
+        };
+        for y in 0..table.height {
+            table.update_row(y);
+        }
+        for x in 0..table.width {
+            table.update_column(x);
+        }
+        table
+    }
+
+    fn update_row(&mut self, y: usize) {
+        let row = (y * self.width)..((y + 1) * self.width);
+        let mut east = None;
+        for cell in row.clone().rev() {
+            self.stops[0][cell] = east;
+            if self.blocked[cell] {
+                east = cell.checked_sub(1);
+            }
+        }
+        let mut west = None;
+        for cell in row.clone() {
+            self.stops[2][cell] = west;
+            if self.blocked[cell] {
+                west = Some(cell + 1);
+            }
+        }
+    }
+
+    fn update_column(&mut self, x: usize) {
+        let column = (0..self.height).map(|y| y * self.width + x);
+        let mut south = None;
+        for cell in column.clone().rev() {
+            self.stops[1][cell] = south;
+            if self.blocked[cell] {
+                south = cell.checked_sub(self.width);
+            }
+        }
+        let mut north = None;
+        for cell in column {
+            self.stops[3][cell] = north;
+            if self.blocked[cell] {
+                north = Some(cell + self.width);
+            }
+        }
+    }
+
+    // Only the row and column of `cell` can stop somewhere else afterwards.
+    fn set_blocked(&mut self, cell: usize, blocked: bool) {
+        self.blocked[cell] = blocked;
+        self.update_row(cell / self.width);
+        self.update_column(cell % self.width);
+    }
+
+    fn walk(&mut self, start: &Day6Player) -> JumpOutcome {
+        let mut cell = (start.y as usize) * self.width + start.x as usize;
+        let mut dir = dir_index(start.dir_y, start.dir_x);
+        let mut touched = vec![];
+        let mut turns_in_place = 0;
+        let outcome = loop {
+            let Some(stop) = self.stops[dir][cell] else {
+                break JumpOutcome::Exit;
+            };
+            if stop == cell {
+                turns_in_place += 1;
+                if turns_in_place == 4 {
+                    break JumpOutcome::Stuck;
+                }
+            } else {
+                turns_in_place = 0;
+            }
+            cell = stop;
+            dir = (dir + 1) % 4;
+            let state = cell * 4 + dir;
+            if !self.seen_states.insert(state) {
+                break JumpOutcome::Loop;
+            }
+            touched.push(state);
+        };
+        for state in touched {
+            self.seen_states.remove(state);
+        }
+        outcome
+    }
 }
 
 fn main() {
     let raw = fs::read_to_string("input.txt")
         .map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))
         .unwrap();
-    let mut map = CharArray::from(&raw);
-    let pos_chars = "><^v";
-    let pos_bytes = pos_chars.bytes().collect::<Vec<u8>>();
-    let start_pos = find_player(&map, pos_chars).unwrap();
+    let map = CharArray::from(&raw);
+    let tiles = parse_tiles(&map).unwrap();
+    let guards = find_guards(&map, GUARDS);
+    let Some(start_pos) = guards.first().cloned() else {
+        eprintln!("ERROR: Could not find player in map");
+        return;
+    };
 
-    let result_part1 = walk_map(&mut map).unwrap().unwrap_or_default();
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if !args.is_empty() {
+        for (idx, player) in guards.into_iter().enumerate() {
+            let turn = args
+                .get(idx)
+                .map(|raw| TurnRule::from(raw))
+                .unwrap_or(Ok(TurnRule::Right))
+                .unwrap();
+            let (x, y) = (player.x, player.y);
+            let guard = Guard { player, turn };
+            let visited = match walk_map(&map, &tiles, &guard, None) {
+                WalkOutcome::Exit { visited } => {
+                    println!("Guard {idx} at ({x}, {y}) turning {turn:?} exits.");
+                    visited
+                }
+                WalkOutcome::Loop {
+                    entry,
+                    length,
+                    visited,
+                } => {
+                    println!(
+                        "Guard {idx} at ({x}, {y}) turning {turn:?} loops from ({ex}, {ey}) every {length} steps.",
+                        ex = entry.x,
+                        ey = entry.y
+                    );
+                    visited
+                }
+                WalkOutcome::Stuck { visited } => {
+                    println!("Guard {idx} at ({x}, {y}) turning {turn:?} is stuck.");
+                    visited
+                }
+            };
+            println!("Covers {count} cells:", count = visited.len());
+            print!("{}", render_covered(&map, &visited));
+        }
+        return;
+    }
+
+    let guard = Guard {
+        player: start_pos.clone(),
+        turn: TurnRule::Right,
+    };
+    let WalkOutcome::Exit { visited } = walk_map(&map, &tiles, &guard, None) else {
+        eprintln!("ERROR: Guard does not leave the map.");
+        return;
+    };
+    let result_part1 = visited.len();
     println!("Day 6, part 1: {result_part1}");
 
+    // The jump table only knows about `#` obstacles.
+    if !tiles.is_plain() {
+        eprintln!("WARNING: Skipping part 2, the map has one-way tiles or teleporters.");
+        return;
+    }
     let start_cur = start_pos.y as usize * map.width + start_pos.x as usize;
-    let candidates = map
-        .contents
-        .iter()
-        .enumerate()
-        .filter(|(i, c)| *i != start_cur && pos_bytes.contains(c))
-        .map(|(i, _)| i)
-        .collect::<Vec<usize>>();
+    let mut table = JumpTable::new(&map);
     let mut result_part2 = 0;
-    let barrier = "#".bytes().next().expect("ascii");
-    for cur in candidates {
-        reset_map(&mut map, &start_pos);
-        let prev = map[cur];
-        map[cur] = barrier;
-        if let Ok(None) = walk_map(&mut map) {
+    for &cur in visited.iter().filter(|&&cur| cur != start_cur) {
+        table.set_blocked(cur, true);
+        if table.walk(&start_pos) == JumpOutcome::Loop {
             result_part2 += 1;
         }
-        map[cur] = prev;
+        table.set_blocked(cur, false);
     }
     println!("Day 6, part 2: {result_part2}");
 }
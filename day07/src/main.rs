@@ -1,4 +1,4 @@
-use std::fs;
+use std::{collections::HashMap, fs};
 
 #[derive(Debug)]
 pub struct Equation {
@@ -6,59 +6,396 @@ pub struct Equation {
     rhs: Vec<u64>,
 }
 
-fn cat(l: u64, r: u64) -> u64 {
-    let n_digits = ((r as f64).log10().floor() as u32) + 1;
-    l * 10u64.pow(n_digits) + r
+fn n_digits(mut r: u64, base: u64) -> u32 {
+    let mut n = 1;
+    while r >= base {
+        r /= base;
+        n += 1;
+    }
+    n
 }
 
-impl Equation {
-    fn is_valid_part1(&self) -> bool {
-        let n = self.rhs.len() - 1;
-        for i in 0..(1 << n) {
-            let mut res = self.rhs[0];
-            for (k, r) in self.rhs[1..].iter().enumerate() {
-                if (i >> k) & 1 == 1 {
-                    res += r;
-                } else {
-                    res *= r;
-                }
-                if res > self.lhs {
-                    break;
-                }
-            }
-            if res == self.lhs {
-                return true;
-            }
+// Left operands `l` with `l op r == target`.
+pub enum Preimage {
+    None,
+    One(u64),
+    Any,
+}
+
+pub trait Operator {
+    fn symbol(&self) -> String;
+    fn apply(&self, l: u64, r: u64) -> Option<u64>;
+    fn undo(&self, target: u64, r: u64) -> Preimage;
+    // An upper bound on `apply(l, r)` over every `l <= hi`, if all of them evaluate.
+    fn bound(&self, hi: u64, r: u64) -> Option<u64>;
+}
+
+fn preimage(l: Option<u64>) -> Preimage {
+    l.map_or(Preimage::None, Preimage::One)
+}
+
+pub struct Add;
+
+impl Operator for Add {
+    fn symbol(&self) -> String {
+        "+".to_owned()
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        l.checked_add(r)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        preimage(target.checked_sub(r))
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        hi.checked_add(r)
+    }
+}
+
+pub struct Mul;
+
+impl Operator for Mul {
+    fn symbol(&self) -> String {
+        "*".to_owned()
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        l.checked_mul(r)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        match (target, r) {
+            (0, 0) => Preimage::Any,
+            (_, 0) => Preimage::None,
+            _ => preimage(target.is_multiple_of(r).then_some(target / r)),
         }
-        false
-    }
-    fn is_valid_part2(&self) -> bool {
-        let n = self.rhs.len() - 1;
-        let m = 3u64.pow(n as u32);
-        for i in 0..m {
-            let mut res = self.rhs[0];
-            for (k, r) in self.rhs[1..].iter().enumerate() {
-                match (i / 3u64.pow(k as u32)) % 3 {
-                    0 => {
-                        res += r;
-                    }
-                    1 => {
-                        res *= r;
-                    }
-                    2 => {
-                        res = cat(res, *r);
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        hi.checked_mul(r)
+    }
+}
+
+pub struct Concat {
+    base: u64,
+}
+
+impl Operator for Concat {
+    fn symbol(&self) -> String {
+        match self.base {
+            10 => "||".to_owned(),
+            base => format!("||{base}"),
+        }
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        self.base
+            .checked_pow(n_digits(r, self.base))?
+            .checked_mul(l)?
+            .checked_add(r)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        let Some(shift) = self.base.checked_pow(n_digits(r, self.base)) else {
+            return Preimage::None;
+        };
+        preimage((target % shift == r).then_some(target / shift))
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        self.apply(hi, r)
+    }
+}
+
+pub struct Sub;
+
+impl Operator for Sub {
+    fn symbol(&self) -> String {
+        "-".to_owned()
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        l.checked_sub(r)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        preimage(target.checked_add(r))
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        (r == 0).then_some(hi)
+    }
+}
+
+pub struct Xor;
+
+impl Operator for Xor {
+    fn symbol(&self) -> String {
+        "^".to_owned()
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        Some(l ^ r)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        Preimage::One(target ^ r)
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        // Never sets a bit above the highest of either side.
+        Some(u64::MAX >> hi.max(r).leading_zeros())
+    }
+}
+
+pub struct Pow;
+
+impl Operator for Pow {
+    fn symbol(&self) -> String {
+        "**".to_owned()
+    }
+
+    fn apply(&self, l: u64, r: u64) -> Option<u64> {
+        l.checked_pow(u32::try_from(r).ok()?)
+    }
+
+    fn undo(&self, target: u64, r: u64) -> Preimage {
+        match (target, r) {
+            (1, 0) => Preimage::Any,
+            (_, 0) => Preimage::None,
+            _ => {
+                // Integer r-th root by bisection, l^r is strictly increasing in l.
+                let (mut lo, mut hi) = (0, target);
+                while lo < hi {
+                    let mid = lo + (hi - lo) / 2;
+                    match self.apply(mid, r) {
+                        Some(v) if v >= target => hi = mid,
+                        Some(_) => lo = mid + 1,
+                        None => hi = mid,
                     }
-                    _ => unreachable!(),
-                }
-                if res > self.lhs {
-                    break;
                 }
+                preimage((self.apply(lo, r) == Some(target)).then_some(lo))
             }
-            if res == self.lhs {
-                return true;
+        }
+    }
+
+    fn bound(&self, hi: u64, r: u64) -> Option<u64> {
+        self.apply(hi, r)
+    }
+}
+
+fn parse_operators(spec: &str) -> Result<Vec<Box<dyn Operator>>, ()> {
+    let mut ops: Vec<Box<dyn Operator>> = vec![];
+    for name in spec.split(",").map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match name {
+            "add" => ops.push(Box::new(Add)),
+            "mul" => ops.push(Box::new(Mul)),
+            "sub" => ops.push(Box::new(Sub)),
+            "xor" => ops.push(Box::new(Xor)),
+            "pow" => ops.push(Box::new(Pow)),
+            "cat" => ops.push(Box::new(Concat { base: 10 })),
+            _ => {
+                let base = name
+                    .strip_prefix("cat")
+                    .and_then(|b| b.parse::<u64>().ok())
+                    .filter(|&b| b >= 2)
+                    .ok_or_else(|| {
+                        eprintln!("ERROR: Unknown operator {name}, expected add, mul, sub, xor, pow or cat<base>.");
+                    })?;
+                ops.push(Box::new(Concat { base }));
             }
         }
-        false
+    }
+    Ok(ops)
+}
+
+// Works right to left, undoing the last operand with the inverse of each operator. Most
+// branches die immediately (negative difference, inexact division, wrong suffix), which
+// keeps long equations tractable where enumerating 3^n assignments is not. `found` gets
+// each assignment as indices into `ops` in left-to-right order and returns whether to keep
+// searching.
+fn search(
+    target: u64,
+    operands: &[u64],
+    ops: &[Box<dyn Operator>],
+    trail: &mut Vec<usize>,
+    found: &mut dyn FnMut(&[usize]) -> bool,
+) -> bool {
+    let Some((&last, rest)) = operands.split_last() else {
+        return true;
+    };
+    if rest.is_empty() {
+        if target == last {
+            let assignment = trail.iter().rev().copied().collect::<Vec<usize>>();
+            return found(&assignment);
+        }
+        return true;
+    }
+    for (idx, op) in ops.iter().enumerate() {
+        trail.push(idx);
+        let more = match op.undo(target, last) {
+            Preimage::None => true,
+            Preimage::One(prev) => search(prev, rest, ops, trail, found),
+            Preimage::Any => enumerate(rest[0], &rest[1..], ops, &mut vec![], trail, found),
+        };
+        trail.pop();
+        if !more {
+            return false;
+        }
+    }
+    true
+}
+
+// Left to right over every assignment that evaluates without overflow, for prefixes whose
+// value does not matter (such as `... * 0 = 0`). `tail` holds the already chosen operators
+// right to left.
+fn enumerate(
+    value: u64,
+    operands: &[u64],
+    ops: &[Box<dyn Operator>],
+    head: &mut Vec<usize>,
+    tail: &[usize],
+    found: &mut dyn FnMut(&[usize]) -> bool,
+) -> bool {
+    let Some((&r, rest)) = operands.split_first() else {
+        let assignment = head
+            .iter()
+            .chain(tail.iter().rev())
+            .copied()
+            .collect::<Vec<usize>>();
+        return found(&assignment);
+    };
+    for (idx, op) in ops.iter().enumerate() {
+        let Some(next) = op.apply(value, r) else {
+            continue;
+        };
+        head.push(idx);
+        let more = enumerate(next, rest, ops, head, tail, found);
+        head.pop();
+        if !more {
+            return false;
+        }
+    }
+    true
+}
+
+// Intermediate values of long prefixes can be all distinct, such as with `3 ... 3 * 0`,
+// so the count gives up rather than exhaust memory.
+const EVALUABLE_LIMIT: usize = 1 << 20;
+
+// Assignments of a prefix that evaluate without overflow, memoised on (operands left,
+// value). Once the bounds show that every assignment from `value` on evaluates, they are
+// counted in closed form instead. None when the prefix has too many distinct values.
+fn count_evaluable(
+    value: u64,
+    operands: &[u64],
+    ops: &[Box<dyn Operator>],
+    memo: &mut HashMap<(usize, u64), u128>,
+) -> Option<u128> {
+    let Some((&r, rest)) = operands.split_first() else {
+        return Some(1);
+    };
+    if let Some(&c) = memo.get(&(operands.len(), value)) {
+        return Some(c);
+    }
+    if memo.len() >= EVALUABLE_LIMIT {
+        return None;
+    }
+    let mut hi = Some(value);
+    for &r in operands {
+        hi = hi.and_then(|hi| {
+            ops.iter()
+                .map(|op| op.bound(hi, r))
+                .try_fold(0, |m, b| b.map(|b| b.max(m)))
+        });
+    }
+    let c = match hi {
+        Some(_) => (ops.len() as u128).saturating_pow(operands.len() as u32),
+        None => ops
+            .iter()
+            .filter_map(|op| op.apply(value, r))
+            .map(|next| count_evaluable(next, rest, ops, memo))
+            .try_fold(0, |c, n| n.map(|n| u128::saturating_add(c, n)))?,
+    };
+    memo.insert((operands.len(), value), c);
+    Some(c)
+}
+
+// Undoing different operators often leads back to the same target, so counts are
+// memoised on (target, operands left). None when some prefix is too costly to count.
+fn count_solutions(target: u64, operands: &[u64], ops: &[Box<dyn Operator>]) -> Option<u128> {
+    fn count(
+        target: u64,
+        operands: &[u64],
+        ops: &[Box<dyn Operator>],
+        memo: &mut HashMap<(u64, usize), u128>,
+        // Per prefix length, as each prefix has its own operands.
+        evaluable: &mut HashMap<usize, HashMap<(usize, u64), u128>>,
+    ) -> Option<u128> {
+        let Some((&last, rest)) = operands.split_last() else {
+            return Some(0);
+        };
+        if rest.is_empty() {
+            return Some((target == last) as u128);
+        }
+        if let Some(&c) = memo.get(&(target, operands.len())) {
+            return Some(c);
+        }
+        let c = ops
+            .iter()
+            .map(|op| match op.undo(target, last) {
+                Preimage::None => Some(0),
+                Preimage::One(prev) => count(prev, rest, ops, memo, evaluable),
+                Preimage::Any => count_evaluable(
+                    rest[0],
+                    &rest[1..],
+                    ops,
+                    evaluable.entry(rest.len()).or_default(),
+                ),
+            })
+            .try_fold(0, |c, n| n.map(|n| u128::saturating_add(c, n)))?;
+        memo.insert((target, operands.len()), c);
+        Some(c)
+    }
+
+    count(
+        target,
+        operands,
+        ops,
+        &mut HashMap::new(),
+        &mut HashMap::new(),
+    )
+}
+
+impl Equation {
+    fn solutions(&self, ops: &[Box<dyn Operator>], limit: usize) -> Vec<Vec<usize>> {
+        let mut solutions = vec![];
+        if limit > 0 {
+            search(self.lhs, &self.rhs, ops, &mut vec![], &mut |assignment| {
+                solutions.push(assignment.to_vec());
+                solutions.len() < limit
+            });
+        }
+        solutions
+    }
+
+    fn is_valid(&self, ops: &[Box<dyn Operator>]) -> bool {
+        !self.solutions(ops, 1).is_empty()
+    }
+
+    fn count_solutions(&self, ops: &[Box<dyn Operator>]) -> Option<u128> {
+        count_solutions(self.lhs, &self.rhs, ops)
+    }
+
+    fn render(&self, ops: &[Box<dyn Operator>], assignment: &[usize]) -> String {
+        let mut rendered = self.rhs[0].to_string();
+        for (&op, r) in assignment.iter().zip(&self.rhs[1..]) {
+            rendered.push_str(&format!(" {} {r}", ops[op].symbol()));
+        }
+        rendered.push_str(&format!(" = {}", self.lhs));
+        rendered
     }
 }
 
@@ -108,14 +445,51 @@ fn parse_equations(file_path: &str) -> Result<Vec<Equation>, ()> {
 
 fn main() {
     let equations = parse_equations("input.txt").unwrap();
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let args = args.iter().map(|a| a.as_str()).collect::<Vec<&str>>();
+    match args.as_slice() {
+        [] => {}
+        ["explain"] | ["explain", _] => {
+            let ops = parse_operators(args.get(1).copied().unwrap_or("add,mul,cat")).unwrap();
+            for eq in &equations {
+                match eq.solutions(&ops, 1).first() {
+                    Some(assignment) => println!(
+                        "{rendered} ({count} ways)",
+                        rendered = eq.render(&ops, assignment),
+                        count = eq
+                            .count_solutions(&ops)
+                            .map_or("too many".to_owned(), |c| c.to_string())
+                    ),
+                    None => println!("{lhs}: no solution", lhs = eq.lhs),
+                }
+            }
+            return;
+        }
+        [spec] => {
+            let ops = parse_operators(spec).unwrap();
+            let result = equations
+                .iter()
+                .filter(|eq| eq.is_valid(&ops))
+                .map(|eq| eq.lhs as u128)
+                .sum::<u128>();
+            println!("Day 7, {spec}: {result}");
+            return;
+        }
+        _ => {
+            eprintln!("ERROR: Unknown command: {}", args.join(" "));
+            return;
+        }
+    }
+    let ops_part1 = parse_operators("add,mul").unwrap();
+    let ops_part2 = parse_operators("add,mul,cat").unwrap();
     let mut result_part1 = 0;
     let mut result_part_2 = 0;
     for eq in equations {
-        if eq.is_valid_part1() {
+        if eq.is_valid(&ops_part1) {
             result_part1 += eq.lhs;
             continue;
         }
-        if eq.is_valid_part2() {
+        if eq.is_valid(&ops_part2) {
             result_part_2 += eq.lhs;
         }
     }
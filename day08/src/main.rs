@@ -1,9 +1,9 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt, fs,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CharArray {
     contents: Vec<u8>,
     width: usize,
@@ -49,10 +49,6 @@ impl CharArray {
             height,
         }
     }
-
-    fn is_valid(&self, x: i32, y: i32) -> bool {
-        x >= 0 && (x as usize) < self.width && y >= 0 && (y as usize) < self.height
-    }
 }
 
 impl fmt::Display for CharArray {
@@ -67,79 +63,305 @@ impl fmt::Display for CharArray {
     }
 }
 
-fn gather_antennas(map: &CharArray) -> HashMap<u8, Vec<usize>> {
-    let dot = ".".bytes().next().expect("ascii char");
+// A bounded lattice of any dimension, each axis going from 0 to its bound (excluded).
+#[derive(Debug)]
+pub struct Lattice {
+    bounds: Vec<i64>,
+    antennas: Vec<(u8, Vec<i64>)>,
+}
+
+impl Lattice {
+    fn from_map(map: &CharArray) -> Self {
+        let dot = ".".bytes().next().expect("ascii char");
+        let antennas = map
+            .contents
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c != dot)
+            .map(|(idx, &c)| (c, vec![(idx % map.width) as i64, (idx / map.width) as i64]))
+            .collect();
+        Self {
+            bounds: vec![map.width as i64, map.height as i64],
+            antennas,
+        }
+    }
+
+    fn contains(&self, point: &[i64]) -> bool {
+        point
+            .iter()
+            .zip(&self.bounds)
+            .all(|(&p, &bound)| p >= 0 && p < bound)
+    }
+}
+
+fn parse_coords(raw: &str, row: usize) -> Result<Vec<i64>, ()> {
+    raw.split(",")
+        .map(|s| s.trim())
+        .map(|s| s.parse())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|e| eprintln!("ERROR: {row}: Failed to parse coordinates: {e}."))
+}
+
+// `bounds: 12,12,4` followed by one `<frequency>: x,y,z` line per antenna.
+fn parse_lattice(raw: &str) -> Result<Lattice, ()> {
+    let mut lines = raw
+        .lines()
+        .map(|l| l.trim())
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty());
+    let bounds = match lines.next() {
+        Some((idx, line)) if line.starts_with("bounds:") => {
+            parse_coords(&line["bounds:".len()..], idx + 1)?
+        }
+        _ => {
+            eprintln!("ERROR: Expected a `bounds:` line first.");
+            return Err(());
+        }
+    };
+    let mut antennas = vec![];
+    let mut seen = HashSet::new();
+    for (idx, line) in lines {
+        let Some((freq, coords)) = line.split_once(":") else {
+            eprintln!(
+                "ERROR: {row}: Expected `frequency: coordinates`.",
+                row = idx + 1
+            );
+            return Err(());
+        };
+        let &[freq] = freq.trim().as_bytes() else {
+            eprintln!(
+                "ERROR: {row}: Frequency should be a single character.",
+                row = idx + 1
+            );
+            return Err(());
+        };
+        let coords = parse_coords(coords, idx + 1)?;
+        if coords.len() != bounds.len() {
+            eprintln!(
+                "ERROR: {row}: Found {found} coordinates instead of {expected}.",
+                row = idx + 1,
+                found = coords.len(),
+                expected = bounds.len()
+            );
+            return Err(());
+        }
+        if !coords
+            .iter()
+            .zip(&bounds)
+            .all(|(&c, &bound)| c >= 0 && c < bound)
+        {
+            eprintln!(
+                "ERROR: {row}: Antenna {freq} lies outside the bounds.",
+                row = idx + 1,
+                freq = freq as char
+            );
+            return Err(());
+        }
+        if !seen.insert((freq, coords.clone())) {
+            eprintln!(
+                "ERROR: {row}: Antenna {freq} appears twice at the same position.",
+                row = idx + 1,
+                freq = freq as char
+            );
+            return Err(());
+        }
+        antennas.push((freq, coords));
+    }
+    Ok(Lattice { bounds, antennas })
+}
+
+fn gather_antennas(lattice: &Lattice) -> HashMap<u8, Vec<Vec<i64>>> {
     let mut antennas = HashMap::new();
-    for (idx, c) in map.contents.iter().enumerate().filter(|(_, &c)| c != dot) {
+    for (freq, position) in &lattice.antennas {
         antennas
-            .entry(*c)
-            .and_modify(|v: &mut Vec<usize>| v.push(idx))
-            .or_insert(vec![idx]);
+            .entry(*freq)
+            .and_modify(|v: &mut Vec<Vec<i64>>| v.push(position.clone()))
+            .or_insert(vec![position.clone()]);
     }
     antennas
 }
 
-fn antinodes_from_pair(map: &CharArray, fst: usize, snd: usize, shallow: bool) -> Vec<usize> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // One antinode on each side of the pair, at the pair's distance.
+    Single,
+    // Every multiple of the pair's offset, which skips collinear points in between when
+    // dx and dy share a factor. As originally written, the antennas themselves only count
+    // when the pair produces at least one other antinode.
+    Harmonics,
+    // Every grid point exactly in line with the pair.
+    Lattice,
+}
+
+impl Mode {
+    fn from(raw: &str) -> Result<Self, ()> {
+        match raw {
+            "single" => Ok(Mode::Single),
+            "harmonics" => Ok(Mode::Harmonics),
+            "lattice" => Ok(Mode::Lattice),
+            _ => {
+                eprintln!("ERROR: Unknown mode {raw}, expected single, harmonics or lattice.");
+                Err(())
+            }
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn offset(point: &[i64], delta: &[i64], k: i64) -> Vec<i64> {
+    point.iter().zip(delta).map(|(&p, &d)| p + k * d).collect()
+}
+
+fn walk_line(lattice: &Lattice, start: &[i64], delta: &[i64], res: &mut Vec<Vec<i64>>) {
+    let mut k = 1;
+    loop {
+        let point = offset(start, delta, k);
+        if !lattice.contains(&point) {
+            break;
+        }
+        res.push(point);
+        k += 1;
+    }
+}
+
+fn antinodes_from_pair(lattice: &Lattice, fst: &[i64], snd: &[i64], mode: Mode) -> Vec<Vec<i64>> {
     let mut res = vec![];
-    let width = map.width;
-    let fst_y = fst / width;
-    let fst_x = fst % width;
-    let snd_y = snd / width;
-    let snd_x = snd % width;
-    let dx = fst_x as i32 - snd_x as i32;
-    let dy = fst_y as i32 - snd_y as i32;
-    let mut ax = fst_x as i32 + dx;
-    let mut ay = fst_y as i32 + dy;
-    let mut steps = 1;
-
-    for step_dir in [1, -1] {
-        // my kingdom for a do while
-        loop {
-            if map.is_valid(ax, ay) {
-                res.push((ay as usize) * width + ax as usize);
-            } else {
-                break;
+    let delta = fst
+        .iter()
+        .zip(snd)
+        .map(|(f, s)| f - s)
+        .collect::<Vec<i64>>();
+    match mode {
+        Mode::Single => {
+            for point in [offset(fst, &delta, 1), offset(snd, &delta, -1)] {
+                if lattice.contains(&point) {
+                    res.push(point);
+                }
             }
-            if shallow {
-                break;
+        }
+        Mode::Harmonics => {
+            let back = delta.iter().map(|d| -d).collect::<Vec<i64>>();
+            walk_line(lattice, fst, &delta, &mut res);
+            walk_line(lattice, snd, &back, &mut res);
+            if !res.is_empty() {
+                res.extend([fst.to_vec(), snd.to_vec()]);
             }
-            ax += step_dir * dx;
-            ay += step_dir * dy;
-            steps += 1;
         }
-        ax -= (steps + 2) * dx;
-        ay -= (steps + 2) * dy;
+        Mode::Lattice => {
+            let g = delta.iter().fold(0, |g, &d| gcd(g, d));
+            let step = delta.iter().map(|d| d / g).collect::<Vec<i64>>();
+            let back = step.iter().map(|d| -d).collect::<Vec<i64>>();
+            res.push(fst.to_vec());
+            walk_line(lattice, fst, &step, &mut res);
+            walk_line(lattice, fst, &back, &mut res);
+        }
     }
     res
 }
 
-fn count_antinodes(map: &CharArray, shallow: bool) -> usize {
-    let antennas = gather_antennas(map);
-    let mut antinodes = HashSet::new();
-    for (_, positions) in antennas {
-        for (idx, &fst) in positions[..(positions.len() - 1)].iter().enumerate() {
-            for &snd in positions[(idx + 1)..].iter() {
-                let mut pair_anti = antinodes_from_pair(map, fst, snd, shallow);
-                if !shallow && !pair_anti.is_empty() {
-                    pair_anti.push(fst);
-                    pair_anti.push(snd);
-                }
-                for antinode in pair_anti {
-                    let _ = antinodes.insert(antinode);
-                }
+fn antinodes_by_frequency(lattice: &Lattice, mode: Mode) -> BTreeMap<u8, HashSet<Vec<i64>>> {
+    let antennas = gather_antennas(lattice);
+    let mut by_frequency = BTreeMap::new();
+    for (freq, positions) in antennas {
+        let antinodes: &mut HashSet<Vec<i64>> = by_frequency.entry(freq).or_default();
+        for (idx, fst) in positions.iter().enumerate() {
+            for snd in positions[(idx + 1)..].iter() {
+                antinodes.extend(antinodes_from_pair(lattice, fst, snd, mode));
             }
         }
     }
-    antinodes.len()
+    by_frequency
+}
+
+fn count_antinodes(lattice: &Lattice, mode: Mode) -> usize {
+    antinodes_by_frequency(lattice, mode)
+        .into_values()
+        .flatten()
+        .collect::<HashSet<Vec<i64>>>()
+        .len()
+}
+
+pub struct FrequencyReport {
+    frequency: u8,
+    antennas: usize,
+    antinodes: usize,
+    shared: usize,
+}
+
+fn frequency_report(lattice: &Lattice, mode: Mode) -> Vec<FrequencyReport> {
+    let antennas = gather_antennas(lattice);
+    let by_frequency = antinodes_by_frequency(lattice, mode);
+    let mut owners: HashMap<&[i64], usize> = HashMap::new();
+    for antinode in by_frequency.values().flatten() {
+        *owners.entry(antinode).or_default() += 1;
+    }
+    by_frequency
+        .iter()
+        .map(|(&frequency, antinodes)| FrequencyReport {
+            frequency,
+            antennas: antennas[&frequency].len(),
+            antinodes: antinodes.len(),
+            shared: antinodes
+                .iter()
+                .filter(|a| owners[a.as_slice()] > 1)
+                .count(),
+        })
+        .collect()
+}
+
+// Antennas stay visible where they overlap an antinode, as in the puzzle illustrations.
+fn render_antinodes(map: &CharArray, mode: Mode) -> CharArray {
+    let dot = ".".bytes().next().expect("ascii char");
+    let hash = "#".bytes().next().expect("ascii char");
+    let mut rendered = map.clone();
+    for antinode in antinodes_by_frequency(&Lattice::from_map(map), mode)
+        .into_values()
+        .flatten()
+    {
+        let antinode = (antinode[1] as usize) * map.width + antinode[0] as usize;
+        if rendered[antinode] == dot {
+            rendered[antinode] = hash;
+        }
+    }
+    rendered
 }
 
 fn main() {
     let raw = fs::read_to_string("input.txt")
         .map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))
         .unwrap();
-    let map = CharArray::from(&raw);
-    let fst = count_antinodes(&map, true);
+    let (lattice, map) = if raw.trim_start().starts_with("bounds:") {
+        (parse_lattice(&raw).unwrap(), None)
+    } else {
+        let map = CharArray::from(&raw);
+        (Lattice::from_map(&map), Some(map))
+    };
+    if let Some(raw_mode) = std::env::args().nth(1) {
+        let mode = Mode::from(&raw_mode).unwrap();
+        for report in frequency_report(&lattice, mode) {
+            println!(
+                "{freq}: {antennas} antennas, {antinodes} antinodes, {shared} shared",
+                freq = report.frequency as char,
+                antennas = report.antennas,
+                antinodes = report.antinodes,
+                shared = report.shared
+            );
+        }
+        println!("Total: {}", count_antinodes(&lattice, mode));
+        if let Some(map) = map {
+            println!("{}", render_antinodes(&map, mode));
+        }
+        return;
+    }
+    let fst = count_antinodes(&lattice, Mode::Single);
     println!("Day 8, part 1: {fst}");
-    let snd = count_antinodes(&map, false);
+    let snd = count_antinodes(&lattice, Mode::Lattice);
     println!("Day 8, part 2: {snd}");
 }
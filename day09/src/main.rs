@@ -1,124 +1,402 @@
-use std::fs;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+};
 
-fn parse_input(filepath: &str) -> Result<Vec<u8>, ()> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // One digit per entry, as in the puzzle.
+    Dense,
+    // Comma-separated lengths, for files and gaps longer than 9 blocks. A trailing comma
+    // marks a single entry, which would otherwise read as dense digits.
+    Extended,
+}
+
+fn parse_input(filepath: &str) -> Result<(Vec<usize>, Format), ()> {
     let data =
         fs::read_to_string(filepath).map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))?;
-    Ok(data
+    let data = data.trim();
+    if data.contains(",") {
+        let lengths = data
+            .strip_suffix(",")
+            .unwrap_or(data)
+            .split(",")
+            .enumerate()
+            .map(|(idx, entry)| {
+                entry.trim().parse::<usize>().map_err(|e| {
+                    eprintln!(
+                        "{filepath}: ERROR: Invalid length {entry:?} for entry {idx}: {e}.",
+                        idx = idx + 1
+                    );
+                })
+            })
+            .collect::<Result<Vec<usize>, ()>>()?;
+        return Ok((lengths, Format::Extended));
+    }
+    let lengths = data
         .chars()
-        .filter(|c| c.is_ascii_digit())
-        .map(|c| c.to_digit(10).expect("digit") as u8)
-        .collect())
+        .enumerate()
+        .map(|(idx, c)| {
+            c.to_digit(10).map(|d| d as usize).ok_or_else(|| {
+                eprintln!(
+                    "{filepath}: ERROR: Unexpected character {c:?} at offset {idx}, expected a digit."
+                );
+            })
+        })
+        .collect::<Result<Vec<usize>, ()>>()?;
+    Ok((lengths, Format::Dense))
 }
 
-fn to_blocks(disk_map: &[u8]) -> Vec<i64> {
-    let mut res = vec![];
-    for (idx, c) in disk_map.iter().enumerate() {
-        let to_push = match idx % 2 {
-            0 => idx as i64 / 2,
-            1 => -1,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    File { id: usize, len: usize },
+    Free { len: usize },
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        match *self {
+            Segment::File { len, .. } | Segment::Free { len } => len,
+        }
+    }
+}
+
+fn to_segments(disk_map: &[usize]) -> Vec<Segment> {
+    let segments = disk_map
+        .iter()
+        .enumerate()
+        .map(|(idx, &len)| match idx % 2 {
+            0 => Segment::File { id: idx / 2, len },
+            1 => Segment::Free { len },
             _ => unreachable!(),
-        };
-        for _ in 0..*c {
-            res.push(to_push);
+        })
+        .collect::<Vec<Segment>>();
+    normalize(segments)
+}
+
+// Drops empty segments and merges neighbours holding the same thing.
+fn normalize(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut res: Vec<Segment> = Vec::with_capacity(segments.len());
+    for segment in segments.into_iter().filter(|s| s.len() > 0) {
+        match (res.last_mut(), segment) {
+            (Some(Segment::Free { len }), Segment::Free { len: more }) => *len += more,
+            (
+                Some(Segment::File { id, len }),
+                Segment::File {
+                    id: other,
+                    len: more,
+                },
+            ) if *id == other => *len += more,
+            _ => res.push(segment),
         }
     }
     res
 }
 
-fn compact_part1(blocks: &mut [i64]) {
-    let mut fst_empty = 0;
-    for i in 0..blocks.len() {
-        let cursor = blocks.len() - 1 - i;
-        if fst_empty >= cursor {
-            return;
-        }
-        if blocks[cursor] < 0 {
+fn disk_len(segments: &[Segment]) -> usize {
+    segments.iter().map(|s| s.len()).sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    id: usize,
+    from: usize,
+    to: usize,
+    len: usize,
+}
+
+fn compact_part1(segments: &[Segment]) -> (Vec<Segment>, Vec<Move>) {
+    let total = disk_len(segments);
+    let mut pending = segments.iter().copied().collect::<VecDeque<Segment>>();
+    let mut res = vec![];
+    let mut moves = vec![];
+    // Offsets of the end of `res` and of the end of `pending`.
+    let (mut front, mut back) = (0, total);
+    while let Some(segment) = pending.pop_front() {
+        let Segment::Free { len } = segment else {
+            res.push(segment);
+            front += segment.len();
             continue;
+        };
+        let mut remaining = len;
+        while remaining > 0 {
+            let Some(tail) = pending.pop_back() else {
+                break;
+            };
+            back -= tail.len();
+            let Segment::File { id, len } = tail else {
+                continue;
+            };
+            let moved = len.min(remaining);
+            res.push(Segment::File { id, len: moved });
+            moves.push(Move {
+                id,
+                from: back + len - moved,
+                to: front,
+                len: moved,
+            });
+            front += moved;
+            remaining -= moved;
+            if len > moved {
+                pending.push_back(Segment::File {
+                    id,
+                    len: len - moved,
+                });
+                back += len - moved;
+            }
         }
-        if let Some(idx) = blocks[fst_empty..]
-            .iter()
-            .enumerate()
-            .filter(|(_, &c)| c < 0)
-            .map(|(idx, _)| idx)
-            .next()
-        {
-            fst_empty += idx;
-        } else {
-            // no free space, nothing to do
-            return;
-        }
-        blocks.swap(cursor, fst_empty);
-        fst_empty += 1;
-    }
-}
-
-fn compact_part2(blocks: &mut [i64]) {
-    let mut cursor = blocks.len() - 1;
-    let mut last_contiguous = 0;
-    loop {
-        if blocks[cursor] < 0 {
-            cursor -= 1;
+    }
+    let used = disk_len(&res);
+    res.push(Segment::Free { len: total - used });
+    (normalize(res), moves)
+}
+
+// Free spans in disk order under a segment tree of their largest size, so the leftmost
+// span that fits is found by descending towards the left whenever it is big enough.
+// A file only ever takes the front of a span, which keeps the spans in order.
+struct FreeIndex {
+    spans: Vec<(usize, usize)>,
+    largest: Vec<usize>,
+    leaves: usize,
+}
+
+impl FreeIndex {
+    fn new(spans: Vec<(usize, usize)>) -> Self {
+        let leaves = spans.len().next_power_of_two();
+        let mut largest = vec![0; 2 * leaves];
+        for (idx, &(_, size)) in spans.iter().enumerate() {
+            largest[leaves + idx] = size;
+        }
+        for node in (1..leaves).rev() {
+            largest[node] = largest[2 * node].max(largest[2 * node + 1]);
+        }
+        Self {
+            spans,
+            largest,
+            leaves,
+        }
+    }
+
+    // Takes `size` blocks from the leftmost span that has them and starts before `limit`.
+    fn take(&mut self, size: usize, limit: usize) -> Option<usize> {
+        if self.spans.is_empty() || self.largest[1] < size {
+            return None;
+        }
+        let mut node = 1;
+        while node < self.leaves {
+            node = if self.largest[2 * node] >= size {
+                2 * node
+            } else {
+                2 * node + 1
+            };
+        }
+        let span = &mut self.spans[node - self.leaves];
+        let start = span.0;
+        if start >= limit {
+            return None;
+        }
+        span.0 += size;
+        span.1 -= size;
+        self.largest[node] = span.1;
+        while node > 1 {
+            node /= 2;
+            self.largest[node] = self.largest[2 * node].max(self.largest[2 * node + 1]);
+        }
+        Some(start)
+    }
+}
+
+fn compact_part2(segments: &[Segment]) -> (Vec<Segment>, Vec<Move>) {
+    let mut files = vec![];
+    let mut moves = vec![];
+    let mut spans = vec![];
+    let mut offset = 0;
+    for &segment in segments {
+        match segment {
+            Segment::File { id, len } => files.push((offset, id, len)),
+            Segment::Free { len } => spans.push((offset, len)),
+        }
+        offset += segment.len();
+    }
+    let mut free = FreeIndex::new(spans);
+    // Files only ever move left, so the space they leave behind is never worth indexing.
+    for file in files.iter_mut().rev() {
+        let (file_start, id, file_size) = *file;
+        let Some(start) = free.take(file_size, file_start) else {
             continue;
+        };
+        file.0 = start;
+        moves.push(Move {
+            id,
+            from: file_start,
+            to: start,
+            len: file_size,
+        });
+    }
+    files.sort();
+    let mut res = vec![];
+    let mut offset = 0;
+    for (start, id, len) in files {
+        res.push(Segment::Free {
+            len: start - offset,
+        });
+        res.push(Segment::File { id, len });
+        offset = start + len;
+    }
+    res.push(Segment::Free {
+        len: disk_len(segments) - offset,
+    });
+    (normalize(res), moves)
+}
+
+// One character per block, so only meant for small disks. Ids past 9 wrap around.
+fn render(segments: &[Segment]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(disk_len(segments));
+    for segment in segments {
+        let c = match *segment {
+            Segment::File { id, .. } => b'0' + (id % 10) as u8,
+            Segment::Free { .. } => b'.',
+        };
+        res.extend(std::iter::repeat_n(c, segment.len()));
+    }
+    res
+}
+
+// The disk as rendered after each move, starting from `segments`.
+fn trace<'a>(
+    segments: &[Segment],
+    moves: &'a [Move],
+) -> impl Iterator<Item = (&'a Move, String)> + 'a {
+    moves.iter().scan(render(segments), |disk, m| {
+        for k in 0..m.len {
+            disk.swap(m.from + k, m.to + k);
         }
-        let block_value = blocks[cursor];
-        let mut block_size = 0;
-        while blocks[cursor] == block_value {
-            block_size += 1;
-            if cursor == 0 {
-                return;
-            }
-            cursor -= 1;
+        Some((m, String::from_utf8_lossy(disk).into_owned()))
+    })
+}
+
+#[derive(Debug)]
+pub struct Fragmentation {
+    // Pieces beyond the first for each file.
+    file_fragments: usize,
+    // Free spans with a file somewhere after them.
+    free_gaps: usize,
+    largest_gap: usize,
+}
+
+fn fragmentation(segments: &[Segment]) -> Fragmentation {
+    let mut pieces: BTreeMap<usize, usize> = BTreeMap::new();
+    for segment in segments {
+        if let Segment::File { id, .. } = segment {
+            *pieces.entry(*id).or_default() += 1;
+        }
+    }
+    let last_file = segments
+        .iter()
+        .rposition(|s| matches!(s, Segment::File { .. }))
+        .unwrap_or(0);
+    let gaps = segments[..last_file]
+        .iter()
+        .filter(|s| matches!(s, Segment::Free { .. }))
+        .map(|s| s.len())
+        .collect::<Vec<usize>>();
+    Fragmentation {
+        file_fragments: pieces.values().map(|p| p - 1).sum(),
+        free_gaps: gaps.len(),
+        largest_gap: gaps.into_iter().max().unwrap_or(0),
+    }
+}
+
+fn blocks_to_result(segments: &[Segment]) -> u128 {
+    let mut offset = 0;
+    let mut result = 0;
+    for segment in segments {
+        if let &Segment::File { id, len } = segment {
+            // id * (offset + (offset + 1) + ... + (offset + len - 1))
+            let (start, len) = (offset as u128, len as u128);
+            result += id as u128 * (len * start + len * len.saturating_sub(1) / 2);
         }
-        cursor += 1;
-        let mut fst_empty = last_contiguous;
-        let mut non_contig = false;
+        offset += segment.len();
+    }
+    result
+}
+
+// File ids are implied by position in both formats, so a compacted disk reads back with
+// its files renumbered. Runs longer than the format allows are split around empty
+// segments of the other kind.
+fn to_disk_map(segments: &[Segment], format: Format) -> String {
+    let max_len = match format {
+        Format::Dense => 9,
+        Format::Extended => usize::MAX,
+    };
+    let mut lengths = vec![];
+    for segment in segments {
+        let is_file = matches!(segment, Segment::File { .. });
+        let mut len = segment.len();
         loop {
-            while fst_empty < cursor && blocks[fst_empty] >= 0 {
-                fst_empty += 1;
+            if lengths.len().is_multiple_of(2) != is_file {
+                lengths.push(0);
             }
-            if !non_contig {
-                last_contiguous = fst_empty;
-                non_contig = true;
-                if last_contiguous >= cursor {
-                    return;
-                }
-            }
-            if fst_empty >= cursor {
+            let chunk = len.min(max_len);
+            lengths.push(chunk);
+            len -= chunk;
+            if len == 0 {
                 break;
             }
-            let mut size_empty = 0;
-            while blocks[fst_empty + size_empty] < 0 {
-                size_empty += 1;
-            }
-            if size_empty >= block_size {
-                for k in 0..block_size {
-                    blocks.swap(cursor + k, fst_empty + k)
-                }
-                break;
+        }
+    }
+    let lengths = lengths.iter().map(|l| l.to_string());
+    match format {
+        Format::Dense => lengths.collect::<String>(),
+        Format::Extended => {
+            let lengths = lengths.collect::<Vec<String>>();
+            match lengths.len() {
+                1 => format!("{},", lengths[0]),
+                _ => lengths.join(","),
             }
-            fst_empty += size_empty;
         }
-        cursor -= 1;
     }
 }
 
-fn blocks_to_result(blocks: &[i64]) -> u64 {
-    blocks
-        .iter()
-        .enumerate()
-        .filter(|(_, &c)| c >= 0)
-        .map(|(i, &c)| i as u64 * c as u64)
-        .sum()
-}
-
 fn main() {
-    let input = parse_input("input.txt").unwrap();
-    let mut blocks = to_blocks(&input);
-    let mut blocks1 = blocks.clone();
-    compact_part1(&mut blocks1);
-    let fst = blocks_to_result(&blocks1);
+    let (input, format) = parse_input("input.txt").unwrap();
+    let segments = to_segments(&input);
+    let (compacted1, moves1) = compact_part1(&segments);
+    let fst = blocks_to_result(&compacted1);
     println!("Day 9, part 1: {fst}");
-    compact_part2(&mut blocks);
-    let snd = blocks_to_result(&blocks);
+    let (compacted2, moves2) = compact_part2(&segments);
+    let snd = blocks_to_result(&compacted2);
     println!("Day 9, part 2: {snd}");
+    match std::env::args().nth(1).as_deref() {
+        Some("dump") => {
+            println!("{}", to_disk_map(&compacted1, format));
+            println!("{}", to_disk_map(&compacted2, format));
+        }
+        Some("trace") => {
+            for (name, moves, compacted) in [
+                ("part 1", &moves1, &compacted1),
+                ("part 2", &moves2, &compacted2),
+            ] {
+                println!("{name}:");
+                println!("{}", String::from_utf8_lossy(&render(&segments)));
+                for (m, disk) in trace(&segments, moves) {
+                    println!(
+                        "{disk}  file {id}: {len} blocks {from} -> {to}",
+                        id = m.id,
+                        len = m.len,
+                        from = m.from,
+                        to = m.to
+                    );
+                }
+                let frag = fragmentation(compacted);
+                println!(
+                    "{fragments} file fragments, {gaps} free gaps, largest gap {largest}",
+                    fragments = frag.file_fragments,
+                    gaps = frag.free_gaps,
+                    largest = frag.largest_gap
+                );
+            }
+        }
+        _ => {}
+    }
 }
@@ -1,4 +1,4 @@
-use std::{collections::HashSet, fs};
+use std::fs;
 
 #[derive(Debug)]
 pub struct CharArray {
@@ -18,25 +18,26 @@ where
     }
 }
 
+const IMPASSABLE: u8 = u8::MAX;
+
+fn parse_cell(c: char) -> Option<u8> {
+    match c {
+        '.' => Some(IMPASSABLE),
+        _ => c.to_digit(10).map(|d| d as u8),
+    }
+}
+
 impl CharArray {
     fn from(raw: &str) -> Self {
         let mut lines = raw.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
         let first = lines
             .next()
             .expect("Should have at least a non-empty line.");
-        let mut contents = first
-            .chars()
-            .filter(|c| c.is_ascii_digit())
-            .map(|c| c.to_digit(10).expect("ascii") as u8)
-            .collect::<Vec<u8>>();
+        let mut contents = first.chars().filter_map(parse_cell).collect::<Vec<u8>>();
         let width = first.len();
         let mut height = 1;
         for line in lines {
-            contents.extend(
-                line.chars()
-                    .filter(|c| c.is_ascii_digit())
-                    .map(|c| c.to_digit(10).expect("ascii") as u8),
-            );
+            contents.extend(line.chars().filter_map(parse_cell));
             height += 1;
         }
         Self {
@@ -51,65 +52,477 @@ impl CharArray {
     }
 }
 
-fn count_trailheads_from_pos(map: &CharArray, y: usize, x: usize, acc: u8) -> HashSet<usize> {
-    if acc == 9 {
-        let mut result = HashSet::new();
-        let _ = result.insert(y * map.width + x);
-        return result;
+// Height differences allowed between consecutive cells. A trail ends at its first summit.
+#[derive(Debug)]
+pub struct TrailRules {
+    start: u8,
+    end: u8,
+    steps: Vec<i8>,
+    diagonals: bool,
+}
+
+impl Default for TrailRules {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            end: 9,
+            steps: vec![1],
+            diagonals: false,
+        }
     }
-    let mut res = HashSet::new();
-    for (dir_x, dir_y) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
-        let nx = x as i32 + dir_x;
-        let ny = y as i32 + dir_y;
-        if map.is_valid(nx, ny) && map[ny as usize * map.width + nx as usize] == acc + 1 {
-            for c in count_trailheads_from_pos(map, ny as usize, nx as usize, acc + 1) {
-                let _ = res.insert(c);
+}
+
+fn parse_height(raw: &str) -> Result<u8, ()> {
+    raw.parse::<u8>()
+        .ok()
+        .filter(|&h| h <= 9)
+        .ok_or_else(|| eprintln!("ERROR: Invalid height {raw}, expected 0 to 9."))
+}
+
+fn parse_step(raw: &str) -> Result<i8, ()> {
+    raw.trim_start_matches("+")
+        .parse::<i8>()
+        .ok()
+        .filter(|&s| (-9..=9).contains(&s))
+        .ok_or_else(|| eprintln!("ERROR: Invalid step {raw}, expected -9 to +9."))
+}
+
+// Options are `start=H`, `end=H`, `steps=+1,+2`, `upto=N` (up by at most N, so flat and
+// descending moves too) and `diagonals`.
+fn parse_rules(args: &[String]) -> Result<TrailRules, ()> {
+    let mut rules = TrailRules::default();
+    for arg in args {
+        match arg.split_once("=") {
+            Some(("start", h)) => rules.start = parse_height(h)?,
+            Some(("end", h)) => rules.end = parse_height(h)?,
+            Some(("steps", steps)) => {
+                rules.steps = steps.split(",").map(parse_step).collect::<Result<_, _>>()?
+            }
+            Some(("upto", n)) => rules.steps = (-9..=parse_step(n)?).collect(),
+            None if arg == "diagonals" => rules.diagonals = true,
+            _ => {
+                eprintln!("ERROR: Unknown trail option {arg}.");
+                return Err(());
             }
         }
     }
-    res
+    if rules.start > rules.end {
+        eprintln!(
+            "ERROR: Start height {start} is above end height {end}.",
+            start = rules.start,
+            end = rules.end
+        );
+        return Err(());
+    }
+    Ok(rules)
+}
+
+impl TrailRules {
+    fn neighbours(&self) -> &'static [(i32, i32)] {
+        if self.diagonals {
+            &[
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (-1, 1),
+                (1, -1),
+                (1, 1),
+            ]
+        } else {
+            &[(-1, 0), (1, 0), (0, -1), (0, 1)]
+        }
+    }
+
+    fn allows(&self, from: u8, to: u8) -> bool {
+        let delta = to as i16 - from as i16;
+        from != IMPASSABLE
+            && to != IMPASSABLE
+            && to <= self.end
+            && self.steps.iter().any(|&s| s as i16 == delta)
+    }
+
+    // Only climbing trails are acyclic, which the height-ordered passes rely on.
+    fn is_climbing(&self) -> bool {
+        self.steps.iter().all(|&s| s > 0)
+    }
+
+    fn moves<'a>(&'a self, map: &'a CharArray, idx: usize) -> impl Iterator<Item = usize> + 'a {
+        let (x, y) = ((idx % map.width) as i32, (idx / map.width) as i32);
+        self.neighbours()
+            .iter()
+            .map(move |(dir_x, dir_y)| (x + dir_x, y + dir_y))
+            .filter(|&(nx, ny)| map.is_valid(nx, ny))
+            .map(|(nx, ny)| ny as usize * map.width + nx as usize)
+            .filter(move |&next| self.allows(map[idx], map[next]))
+    }
 }
 
-fn count_ratings_from_pos(map: &CharArray, y: usize, x: usize, acc: u8) -> u32 {
-    if acc == 9 {
-        return 1;
+// Per cell, the number of distinct trails up to a summit (rating) and the number of
+// summits reachable (score).
+pub struct Trails {
+    ratings: Vec<u64>,
+    scores: Vec<usize>,
+}
+
+// Goes down from the summits one height at a time, so every cell only looks at its
+// already finished neighbours. Summits are tracked 64 at a time, one bit each, and a
+// climbing trail never strays further than `end - start` cells from its summit, so each
+// batch only visits the box around its summits. Memory stays one word per cell.
+fn compute_trails(map: &CharArray, rules: &TrailRules) -> Trails {
+    if !rules.is_climbing() {
+        return compute_simple_trails(map, rules);
     }
-    let mut res = 0;
-    for (dir_x, dir_y) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
-        let nx = x as i32 + dir_x;
-        let ny = y as i32 + dir_y;
-        if map.is_valid(nx, ny) && map[ny as usize * map.width + nx as usize] == acc + 1 {
-            res += count_ratings_from_pos(map, ny as usize, nx as usize, acc + 1);
+    let cells = map.contents.len();
+    let (start, end) = (rules.start as usize, rules.end as usize);
+    let mut levels = vec![vec![]; end + 1];
+    for (idx, &h) in map.contents.iter().enumerate() {
+        if h <= rules.end {
+            levels[h as usize].push(idx);
         }
     }
-    res
+    let mut ratings = vec![0u64; cells];
+    let mut scores = vec![0; cells];
+    for &idx in &levels[end] {
+        ratings[idx] = 1;
+        scores[idx] = 1;
+    }
+    for h in (start..end).rev() {
+        for &idx in &levels[h] {
+            for next in rules.moves(map, idx) {
+                ratings[idx] = ratings[idx].saturating_add(ratings[next]);
+            }
+        }
+    }
+
+    let reach = end - start;
+    let mut reached = vec![0u64; cells];
+    let mut by_height = vec![vec![]; end];
+    for batch in levels[end].chunks(64) {
+        let xs = batch.iter().map(|&idx| idx % map.width);
+        let ys = batch.iter().map(|&idx| idx / map.width);
+        let x0 = xs
+            .clone()
+            .min()
+            .expect("batches are not empty")
+            .saturating_sub(reach);
+        let x1 = (xs.max().expect("batches are not empty") + reach).min(map.width - 1);
+        let y0 = ys
+            .clone()
+            .min()
+            .expect("batches are not empty")
+            .saturating_sub(reach);
+        let y1 = (ys.max().expect("batches are not empty") + reach).min(map.height - 1);
+        for y in y0..=y1 {
+            for idx in (y * map.width + x0)..=(y * map.width + x1) {
+                if (start..end).contains(&(map[idx] as usize)) {
+                    by_height[map[idx] as usize].push(idx);
+                }
+            }
+        }
+        for (bit, &idx) in batch.iter().enumerate() {
+            reached[idx] = 1 << bit;
+        }
+        for h in (start..end).rev() {
+            for &idx in &by_height[h] {
+                let bits = rules
+                    .moves(map, idx)
+                    .fold(0, |bits, next| bits | reached[next]);
+                reached[idx] = bits;
+                scores[idx] += bits.count_ones() as usize;
+            }
+        }
+        for y in y0..=y1 {
+            reached[(y * map.width + x0)..=(y * map.width + x1)].fill(0);
+        }
+        for cells in &mut by_height {
+            cells.clear();
+        }
+    }
+    Trails { ratings, scores }
+}
+
+// Cells from which some summit can be reached, ignoring that trails may not revisit a
+// cell. Used to prune the simple path search.
+fn can_finish(map: &CharArray, rules: &TrailRules) -> Vec<bool> {
+    let mut finish = map
+        .contents
+        .iter()
+        .map(|&h| h == rules.end)
+        .collect::<Vec<bool>>();
+    let mut stack = (0..finish.len())
+        .filter(|&idx| finish[idx])
+        .collect::<Vec<usize>>();
+    while let Some(idx) = stack.pop() {
+        // Moves are symmetric, so the cells stepping onto `idx` are among its moves' cells.
+        let (x, y) = ((idx % map.width) as i32, (idx / map.width) as i32);
+        for (dir_x, dir_y) in rules.neighbours() {
+            let nx = x + dir_x;
+            let ny = y + dir_y;
+            if !map.is_valid(nx, ny) {
+                continue;
+            }
+            let prev = ny as usize * map.width + nx as usize;
+            if !finish[prev] && map[prev] != rules.end && rules.allows(map[prev], map[idx]) {
+                finish[prev] = true;
+                stack.push(prev);
+            }
+        }
+    }
+    finish
 }
 
-fn count_trailheads(map: &CharArray) -> usize {
-    let mut res = 0;
-    for (idx, _) in map.contents.iter().enumerate().filter(|(_, &c)| c == 0) {
-        let acc = 0;
-        res += count_trailheads_from_pos(map, idx / map.width, idx % map.width, acc).len();
+// Depth first over trails that never revisit a cell, calling `found` on each one that
+// reaches a summit. Flat and descending steps allow cycles, so there is no shortcut
+// here and the search is exponential in the worst case.
+fn simple_trails(
+    map: &CharArray,
+    rules: &TrailRules,
+    finish: &[bool],
+    on_path: &mut [bool],
+    path: &mut Vec<usize>,
+    found: &mut dyn FnMut(&[usize]),
+) {
+    let idx = *path.last().expect("path starts at the trailhead");
+    if map[idx] == rules.end {
+        found(path);
+        return;
     }
-    res
+    for next in rules.moves(map, idx) {
+        if on_path[next] || !finish[next] {
+            continue;
+        }
+        on_path[next] = true;
+        path.push(next);
+        simple_trails(map, rules, finish, on_path, path, found);
+        path.pop();
+        on_path[next] = false;
+    }
+}
+
+fn for_each_trail(
+    map: &CharArray,
+    rules: &TrailRules,
+    finish: &[bool],
+    start: usize,
+    found: &mut dyn FnMut(&[usize]),
+) {
+    if map[start] != rules.start || !finish[start] {
+        return;
+    }
+    let mut on_path = vec![false; map.contents.len()];
+    on_path[start] = true;
+    simple_trails(map, rules, finish, &mut on_path, &mut vec![start], found);
 }
 
-fn count_ratings(map: &CharArray) -> u32 {
-    let mut res = 0;
-    for (idx, _) in map.contents.iter().enumerate().filter(|(_, &c)| c == 0) {
-        let acc = 0;
-        res += count_ratings_from_pos(map, idx / map.width, idx % map.width, acc);
+// Ratings and scores for trailheads only, other cells have no trail of their own once
+// trails can wander back through them.
+fn compute_simple_trails(map: &CharArray, rules: &TrailRules) -> Trails {
+    let cells = map.contents.len();
+    let finish = can_finish(map, rules);
+    let mut ratings = vec![0u64; cells];
+    let mut scores = vec![0; cells];
+    for start in (0..cells).filter(|&idx| map[idx] == rules.start) {
+        for_each_trail(map, rules, &finish, start, &mut |_| {
+            ratings[start] = ratings[start].saturating_add(1)
+        });
+        let mut seen = vec![false; cells];
+        seen[start] = true;
+        let mut stack = vec![start];
+        while let Some(idx) = stack.pop() {
+            if map[idx] == rules.end {
+                scores[start] += 1;
+                continue;
+            }
+            for next in rules.moves(map, idx) {
+                if !seen[next] {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    Trails { ratings, scores }
+}
+
+fn count_trailheads(map: &CharArray, rules: &TrailRules, trails: &Trails) -> usize {
+    map.contents
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c == rules.start)
+        .map(|(idx, _)| trails.scores[idx])
+        .sum()
+}
+
+fn count_ratings(map: &CharArray, rules: &TrailRules, trails: &Trails) -> u64 {
+    map.contents
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c == rules.start)
+        .map(|(idx, _)| trails.ratings[idx])
+        .sum()
+}
+
+// Every trail from the trailhead at `start`, as (x, y) coordinates. Cells with no trail
+// left to a summit are never entered.
+fn list_trails(
+    map: &CharArray,
+    rules: &TrailRules,
+    trails: &Trails,
+    start: usize,
+) -> Vec<Vec<(usize, usize)>> {
+    fn extend(
+        map: &CharArray,
+        rules: &TrailRules,
+        trails: &Trails,
+        path: &mut Vec<usize>,
+        res: &mut Vec<Vec<(usize, usize)>>,
+    ) {
+        let idx = *path.last().expect("path starts at the trailhead");
+        if map[idx] == rules.end {
+            res.push(
+                path.iter()
+                    .map(|&c| (c % map.width, c / map.width))
+                    .collect(),
+            );
+            return;
+        }
+        let (x, y) = ((idx % map.width) as i32, (idx / map.width) as i32);
+        for (dir_x, dir_y) in rules.neighbours() {
+            let nx = x + dir_x;
+            let ny = y + dir_y;
+            if !map.is_valid(nx, ny) {
+                continue;
+            }
+            let next = ny as usize * map.width + nx as usize;
+            if rules.allows(map[idx], map[next]) && trails.ratings[next] > 0 {
+                path.push(next);
+                extend(map, rules, trails, path, res);
+                path.pop();
+            }
+        }
+    }
+
+    let mut res = vec![];
+    if !rules.is_climbing() {
+        for_each_trail(map, rules, &can_finish(map, rules), start, &mut |path| {
+            res.push(
+                path.iter()
+                    .map(|&c| (c % map.width, c / map.width))
+                    .collect(),
+            )
+        });
+    } else if map[start] == rules.start {
+        extend(map, rules, trails, &mut vec![start], &mut res);
     }
     res
 }
 
+// Trails through a cell are the ways to reach it from any trailhead times the ways to go
+// on to a summit, the latter being its rating.
+fn trail_heatmap(map: &CharArray, rules: &TrailRules, trails: &Trails) -> Vec<u64> {
+    let cells = map.contents.len();
+    if !rules.is_climbing() {
+        let finish = can_finish(map, rules);
+        let mut heat = vec![0u64; cells];
+        for start in 0..cells {
+            for_each_trail(map, rules, &finish, start, &mut |path| {
+                for &c in path {
+                    heat[c] = heat[c].saturating_add(1);
+                }
+            });
+        }
+        return heat;
+    }
+    let mut order = (0..cells)
+        .filter(|&idx| (rules.start..=rules.end).contains(&map[idx]))
+        .collect::<Vec<usize>>();
+    order.sort_by_key(|&idx| map[idx]);
+    let mut from_start = vec![0u64; cells];
+    for idx in order {
+        if map[idx] == rules.start {
+            from_start[idx] = 1;
+        }
+        let (x, y) = ((idx % map.width) as i32, (idx / map.width) as i32);
+        for (dir_x, dir_y) in rules.neighbours() {
+            let nx = x + dir_x;
+            let ny = y + dir_y;
+            if !map.is_valid(nx, ny) {
+                continue;
+            }
+            let next = ny as usize * map.width + nx as usize;
+            if rules.allows(map[idx], map[next]) {
+                from_start[next] = from_start[next].saturating_add(from_start[idx]);
+            }
+        }
+    }
+    from_start
+        .iter()
+        .zip(&trails.ratings)
+        .map(|(&to, &from)| to.saturating_mul(from))
+        .collect()
+}
+
+fn render_heatmap(map: &CharArray, heat: &[u64]) -> String {
+    let cell_width = heat.iter().max().map_or(1, |m| m.to_string().len());
+    let mut rendered = String::new();
+    for row in heat.chunks(map.width) {
+        let line = row
+            .iter()
+            .map(|h| format!("{h:>cell_width$}"))
+            .collect::<Vec<String>>();
+        rendered.push_str(&line.join(" "));
+        rendered.push('\n');
+    }
+    rendered
+}
+
 fn main() {
     let raw = fs::read_to_string("input.txt")
         .map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))
         .unwrap();
     let map = CharArray::from(&raw);
-    let fst = count_trailheads(&map);
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(|a| a.as_str()) {
+        Some("trails") if args.len() >= 3 => {
+            let coord =
+                |raw: &str| -> usize { raw.parse().expect("Coordinate should be an integer") };
+            let (x, y) = (coord(&args[1]), coord(&args[2]));
+            if x >= map.width || y >= map.height {
+                eprintln!(
+                    "ERROR: Trailhead ({x}, {y}) is outside the {width}x{height} map.",
+                    width = map.width,
+                    height = map.height
+                );
+                return;
+            }
+            let rules = parse_rules(&args[3..]).unwrap();
+            let trails = compute_trails(&map, &rules);
+            for trail in list_trails(&map, &rules, &trails, y * map.width + x) {
+                let steps = trail
+                    .iter()
+                    .map(|(x, y)| format!("({x}, {y})"))
+                    .collect::<Vec<String>>();
+                println!("{}", steps.join(" -> "));
+            }
+            return;
+        }
+        Some("heatmap") => {
+            let rules = parse_rules(&args[1..]).unwrap();
+            let trails = compute_trails(&map, &rules);
+            print!(
+                "{}",
+                render_heatmap(&map, &trail_heatmap(&map, &rules, &trails))
+            );
+            return;
+        }
+        _ => {}
+    }
+    let rules = parse_rules(&args).unwrap();
+    let trails = compute_trails(&map, &rules);
+    let fst = count_trailheads(&map, &rules, &trails);
     println!("Day 10, part 1: {fst}");
-    let snd = count_ratings(&map);
+    let snd = count_ratings(&map, &rules, &trails);
     println!("Day 10, part 2: {snd}");
 }
@@ -1,6 +1,9 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+};
 
-fn parse_input() -> Result<Vec<u64>, ()> {
+fn parse_input() -> Result<Vec<u128>, ()> {
     let raw = fs::read_to_string("input.txt")
         .map_err(|e| eprintln!("ERROR: Failed to read file: {e}"))?;
     let line = raw
@@ -12,65 +15,578 @@ fn parse_input() -> Result<Vec<u64>, ()> {
         .map(|s| s.trim())
         .filter(|&c| !c.is_empty())
         .map(|s| s.parse())
-        .collect::<Result<Vec<u64>, _>>()
+        .collect::<Result<Vec<u128>, _>>()
         .map_err(|e| eprintln!("Failed to parse line as a list of integers: {e}.",))?;
     Ok(stones)
 }
 
-fn n_digits(n: u64) -> u32 {
-    ((n as f64).log10().floor() as u32) + 1
+fn n_digits(mut n: u128) -> u32 {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Overflow {
+    // A stone count no longer fits the count type, a wider one may do.
+    Count,
+    // A stone value no longer fits in u128, no count type helps.
+    Value(u128),
+    // `split` hit a stone with an odd number of digits, which has no middle to split at.
+    OddSplit(u128),
+}
+
+pub trait Count: Clone + fmt::Display {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn to_f64(&self) -> f64;
+}
+
+impl Count for u64 {
+    fn zero() -> Self {
+        0
+    }
+
+    fn one() -> Self {
+        1
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        u64::checked_add(*self, *other)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
 }
 
-fn stones_to_counts(stones: &[u64]) -> HashMap<u64, u64> {
+impl Count for u128 {
+    fn zero() -> Self {
+        0
+    }
+
+    fn one() -> Self {
+        1
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        u128::checked_add(*self, *other)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+// Arbitrary-precision unsigned integer, little-endian 64-bit limbs. Counts only ever get
+// added, so that is all it supports besides printing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigCount {
+    limbs: Vec<u64>,
+}
+
+impl Count for BigCount {
+    fn zero() -> Self {
+        Self { limbs: vec![] }
+    }
+
+    fn one() -> Self {
+        Self { limbs: vec![1] }
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        let len = self.limbs.len().max(other.limbs.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = false;
+        for i in 0..len {
+            let a = self.limbs.get(i).copied().unwrap_or(0);
+            let b = other.limbs.get(i).copied().unwrap_or(0);
+            let (sum, c1) = a.overflowing_add(b);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            limbs.push(sum);
+            carry = c1 || c2;
+        }
+        if carry {
+            limbs.push(1);
+        }
+        Some(Self { limbs })
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &l| acc * 2f64.powi(64) + l as f64)
+    }
+}
+
+impl fmt::Display for BigCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Peel off 19 decimal digits at a time, the most a u64 holds.
+        const CHUNK: u64 = 10u64.pow(19);
+        let mut limbs = self.limbs.clone();
+        let mut chunks = vec![];
+        while limbs.iter().any(|&l| l != 0) {
+            let mut rem = 0u128;
+            for limb in limbs.iter_mut().rev() {
+                let cur = (rem << 64) | *limb as u128;
+                *limb = (cur / CHUNK as u128) as u64;
+                rem = cur % CHUNK as u128;
+            }
+            chunks.push(rem as u64);
+        }
+        let Some((last, rest)) = chunks.split_last() else {
+            return write!(f, "0");
+        };
+        write!(f, "{last}")?;
+        for chunk in rest.iter().rev() {
+            write!(f, "{chunk:019}")?;
+        }
+        Ok(())
+    }
+}
+
+fn add_count<C: Count>(counts: &mut HashMap<u128, C>, s: u128, c: &C) -> Result<(), Overflow> {
+    match counts.get_mut(&s) {
+        Some(k) => *k = k.checked_add(c).ok_or(Overflow::Count)?,
+        None => {
+            counts.insert(s, c.clone());
+        }
+    }
+    Ok(())
+}
+
+fn stones_to_counts<C: Count>(stones: &[u128]) -> Result<HashMap<u128, C>, Overflow> {
     let mut blink_counts = HashMap::new();
     for &s in stones {
-        blink_counts.entry(s).and_modify(|c| *c += 1).or_insert(1);
+        add_count(&mut blink_counts, s, &C::one())?;
     }
-    blink_counts
+    Ok(blink_counts)
 }
 
-fn apply_rule(blink_counts: &HashMap<u64, u64>) -> HashMap<u64, u64> {
-    let mut new_counts = HashMap::new();
-    for (&s, &c) in blink_counts {
-        let new_s = match (s, n_digits(s)) {
-            (0, _) => 1,
-            (s, n) if n % 2 == 0 => {
-                let pow = 10u64.pow(n / 2);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predicate {
+    Any,
+    Eq(u64),
+    EvenDigits,
+    OddDigits,
+    DivisibleBy(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Set(u64),
+    Split,
+    Mul(u64),
+    Add(u64),
+}
+
+// The first rule whose predicate holds decides what a stone becomes. A stone no rule
+// matches stays as it is.
+#[derive(Debug)]
+pub struct Rules {
+    rules: Vec<(Predicate, Transform)>,
+}
+
+const DEFAULT_RULES: &str = "
+eq 0 -> set 1
+even_digits -> split
+any -> mul 2024
+";
+
+impl Predicate {
+    fn holds(self, s: u128) -> bool {
+        match self {
+            Predicate::Any => true,
+            Predicate::Eq(n) => s == n as u128,
+            Predicate::EvenDigits => n_digits(s).is_multiple_of(2),
+            Predicate::OddDigits => n_digits(s) % 2 == 1,
+            Predicate::DivisibleBy(k) => s.is_multiple_of(k as u128),
+        }
+    }
+}
+
+impl Transform {
+    fn apply(self, s: u128) -> Result<Vec<u128>, Overflow> {
+        match self {
+            Transform::Set(n) => Ok(vec![n as u128]),
+            Transform::Split => {
+                let n = n_digits(s);
+                if n % 2 == 1 {
+                    return Err(Overflow::OddSplit(s));
+                }
+                let pow = 10u128.pow(n / 2);
                 let tail = s % pow;
-                new_counts.entry(tail).and_modify(|k| *k += c).or_insert(c);
-                (s - tail) / pow
+                Ok(vec![(s - tail) / pow, tail])
+            }
+            Transform::Mul(k) => s
+                .checked_mul(k as u128)
+                .map(|v| vec![v])
+                .ok_or(Overflow::Value(s)),
+            Transform::Add(k) => s
+                .checked_add(k as u128)
+                .map(|v| vec![v])
+                .ok_or(Overflow::Value(s)),
+        }
+    }
+}
+
+fn parse_arg(raw: Option<&str>, row: usize) -> Result<u64, ()> {
+    let Some(raw) = raw else {
+        eprintln!("ERROR: {row}: Missing integer argument.");
+        return Err(());
+    };
+    raw.parse()
+        .map_err(|e| eprintln!("ERROR: {row}: Failed to parse {raw} as an integer: {e}."))
+}
+
+// One `<predicate> -> <transform>` rule per line, `#` starting a comment. Predicates are
+// `any`, `eq N`, `even_digits`, `odd_digits` and `div K`; transforms are `set N`,
+// `split`, `mul K` and `add K`. Splitting a stone with an odd number of digits is an
+// error, so `split` wants an `even_digits` predicate.
+fn parse_rules(spec: &str) -> Result<Rules, ()> {
+    let mut rules = vec![];
+    for (idx, line) in spec.lines().enumerate() {
+        let row = idx + 1;
+        let line = line.split("#").next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((predicate, transform)) = line.split_once("->") else {
+            eprintln!("ERROR: {row}: Expected `predicate -> transform`.");
+            return Err(());
+        };
+        let mut words = predicate.split_whitespace();
+        let predicate = match words.next() {
+            Some("any") => Predicate::Any,
+            Some("eq") => Predicate::Eq(parse_arg(words.next(), row)?),
+            Some("even_digits") => Predicate::EvenDigits,
+            Some("odd_digits") => Predicate::OddDigits,
+            Some("div") => match parse_arg(words.next(), row)? {
+                0 => {
+                    eprintln!("ERROR: {row}: Cannot test divisibility by 0.");
+                    return Err(());
+                }
+                k => Predicate::DivisibleBy(k),
+            },
+            other => {
+                eprintln!("ERROR: {row}: Unknown predicate {other:?}.");
+                return Err(());
+            }
+        };
+        let mut words = transform.split_whitespace();
+        let transform = match words.next() {
+            Some("set") => Transform::Set(parse_arg(words.next(), row)?),
+            Some("split") => Transform::Split,
+            Some("mul") => Transform::Mul(parse_arg(words.next(), row)?),
+            Some("add") => Transform::Add(parse_arg(words.next(), row)?),
+            other => {
+                eprintln!("ERROR: {row}: Unknown transform {other:?}.");
+                return Err(());
             }
-            (s, _) => s * 2024,
         };
-        new_counts.entry(new_s).and_modify(|k| *k += c).or_insert(c);
+        rules.push((predicate, transform));
+    }
+    Ok(Rules { rules })
+}
+
+fn successors(rules: &Rules, s: u128) -> Result<Vec<u128>, Overflow> {
+    match rules.rules.iter().find(|(p, _)| p.holds(s)) {
+        Some((_, transform)) => transform.apply(s),
+        None => Ok(vec![s]),
+    }
+}
+
+fn apply_rule<C: Count>(
+    rules: &Rules,
+    blink_counts: &HashMap<u128, C>,
+) -> Result<HashMap<u128, C>, Overflow> {
+    let mut new_counts = HashMap::new();
+    for (&s, c) in blink_counts {
+        for new_s in successors(rules, s)? {
+            add_count(&mut new_counts, new_s, c)?;
+        }
     }
-    new_counts
+    Ok(new_counts)
 }
 
-fn iter_rule(blink_counts: HashMap<u64, u64>, n_blinks: u32) -> HashMap<u64, u64> {
+fn iter_rule<C: Count>(
+    rules: &Rules,
+    blink_counts: HashMap<u128, C>,
+    n_blinks: u32,
+) -> Result<HashMap<u128, C>, Overflow> {
     let mut counts = blink_counts;
     for _ in 0..n_blinks {
-        counts = apply_rule(&counts);
+        counts = apply_rule(rules, &counts)?;
+    }
+    Ok(counts)
+}
+
+fn total_counts<C: Count>(counts: &HashMap<u128, C>) -> Result<C, Overflow> {
+    counts.values().try_fold(C::zero(), |acc, c| {
+        acc.checked_add(c).ok_or(Overflow::Count)
+    })
+}
+
+// Total stones after each of `blinks`, which must be sorted.
+fn count_stones<C: Count>(
+    rules: &Rules,
+    stones: &[u128],
+    blinks: &[u32],
+) -> Result<Vec<String>, Overflow> {
+    let mut blink_counts = stones_to_counts::<C>(stones)?;
+    let mut done = 0;
+    let mut totals = vec![];
+    for &n in blinks {
+        blink_counts = iter_rule(rules, blink_counts, n - done)?;
+        done = n;
+        totals.push(total_counts(&blink_counts)?.to_string());
+    }
+    Ok(totals)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    Auto,
+    U64,
+    U128,
+    Big,
+}
+
+// In auto mode, counting restarts with the next wider type whenever counts overflow.
+fn count_stones_with(
+    mode: CountMode,
+    rules: &Rules,
+    stones: &[u128],
+    blinks: &[u32],
+) -> Result<Vec<String>, Overflow> {
+    match mode {
+        CountMode::U64 => count_stones::<u64>(rules, stones, blinks),
+        CountMode::U128 => count_stones::<u128>(rules, stones, blinks),
+        CountMode::Big => count_stones::<BigCount>(rules, stones, blinks),
+        CountMode::Auto => count_stones::<u64>(rules, stones, blinks)
+            .or_else(|e| match e {
+                Overflow::Count => count_stones::<u128>(rules, stones, blinks),
+                e => Err(e),
+            })
+            .or_else(|e| match e {
+                Overflow::Count => count_stones::<BigCount>(rules, stones, blinks),
+                e => Err(e),
+            }),
+    }
+}
+
+pub struct Analysis<C> {
+    // Total stones and distinct values after each blink, starting with the input.
+    stones: Vec<C>,
+    distinct: Vec<usize>,
+    // First blink that brings no value never seen before, after which none ever will.
+    closed_at: Option<u32>,
+    values_seen: usize,
+}
+
+fn analyse<C: Count>(
+    rules: &Rules,
+    stones: &[u128],
+    n_blinks: u32,
+) -> Result<Analysis<C>, Overflow> {
+    let mut counts = stones_to_counts::<C>(stones)?;
+    let mut seen = counts.keys().copied().collect::<HashSet<u128>>();
+    let mut totals = vec![total_counts(&counts)?];
+    let mut distinct = vec![counts.len()];
+    let mut closed_at = None;
+    for blink in 1..=n_blinks {
+        counts = apply_rule(rules, &counts)?;
+        let mut fresh = false;
+        for &s in counts.keys() {
+            fresh |= seen.insert(s);
+        }
+        if !fresh && closed_at.is_none() {
+            closed_at = Some(blink);
+        }
+        totals.push(total_counts(&counts)?);
+        distinct.push(counts.len());
     }
-    counts
+    Ok(Analysis {
+        stones: totals,
+        distinct,
+        closed_at,
+        values_seen: seen.len(),
+    })
 }
 
-fn total_counts(counts: &HashMap<u64, u64>) -> u64 {
-    counts.iter().map(|(_, c)| c).sum()
+// Distinct values are only known for whole-input analyses, single stone curves leave the
+// column out.
+fn growth_csv<C: Count>(stones: &[C], distinct: Option<&[usize]>) -> String {
+    let mut csv = match distinct {
+        Some(_) => String::from("blink,stones,distinct_values,growth_rate\n"),
+        None => String::from("blink,stones,growth_rate\n"),
+    };
+    for (blink, count) in stones.iter().enumerate() {
+        let growth = match blink {
+            0 => String::new(),
+            _ => format!("{:.6}", count.to_f64() / stones[blink - 1].to_f64()),
+        };
+        match distinct {
+            Some(distinct) => csv.push_str(&format!(
+                "{blink},{count},{d},{growth}\n",
+                d = distinct[blink]
+            )),
+            None => csv.push_str(&format!("{blink},{count},{growth}\n")),
+        }
+    }
+    csv
+}
+
+// Stones that `s` alone becomes after each of 0 to `n_blinks` blinks. Every value reachable
+// within `n_blinks` is indexed first, which is usually a small closed set, then counts
+// are built bottom-up one blink at a time.
+fn stone_curve<C: Count>(rules: &Rules, s: u128, n_blinks: u32) -> Result<Vec<C>, Overflow> {
+    let mut index = HashMap::from([(s, 0)]);
+    let mut values = vec![s];
+    // Successors of the values less than `n_blinks` deep, the deepest are never needed.
+    let mut succs: Vec<Option<Vec<usize>>> = vec![None];
+    let mut frontier = vec![0];
+    for _ in 0..n_blinks {
+        if frontier.is_empty() {
+            break;
+        }
+        let mut next_frontier = vec![];
+        for v in frontier {
+            let mut next = vec![];
+            for w in successors(rules, values[v])? {
+                let idx = *index.entry(w).or_insert_with(|| {
+                    values.push(w);
+                    succs.push(None);
+                    next_frontier.push(values.len() - 1);
+                    values.len() - 1
+                });
+                next.push(idx);
+            }
+            succs[v] = Some(next);
+        }
+        frontier = next_frontier;
+    }
+    let mut counts = vec![C::one(); values.len()];
+    let mut curve = vec![C::one()];
+    for _ in 0..n_blinks {
+        let mut next_counts = counts.clone();
+        for (v, succ) in succs.iter().enumerate() {
+            if let Some(succ) = succ {
+                let mut total = C::zero();
+                for &w in succ {
+                    total = total.checked_add(&counts[w]).ok_or(Overflow::Count)?;
+                }
+                next_counts[v] = total;
+            }
+        }
+        counts = next_counts;
+        curve.push(counts[0].clone());
+    }
+    Ok(curve)
 }
 
-fn count_stones(stones: &[u64], blinks_part1: u32, blinks_part2: u32) -> (u64, u64) {
-    let mut blink_counts = stones_to_counts(stones);
-    blink_counts = iter_rule(blink_counts, blinks_part1);
-    let fst = total_counts(&blink_counts);
-    blink_counts = iter_rule(blink_counts, blinks_part2 - blinks_part1);
-    let snd = total_counts(&blink_counts);
-    (fst, snd)
+fn report_overflow(e: Overflow) {
+    match e {
+        Overflow::Count => eprintln!("ERROR: Stone counts overflow, try counts=big."),
+        Overflow::Value(s) => eprintln!("ERROR: Stone {s} overflows when transformed."),
+        Overflow::OddSplit(s) => {
+            eprintln!("ERROR: Stone {s} has an odd number of digits to split.")
+        }
+    }
 }
 
 fn main() {
     let stones = parse_input().unwrap();
-    let (fst, snd) = count_stones(&stones, 25, 75);
-    println!("Day 11, part 1: {fst}");
-    println!("Day 11, part 2: {snd}");
+    let mut spec = DEFAULT_RULES.to_owned();
+    let mut mode = CountMode::Auto;
+    let mut blinks = vec![25, 75];
+    let mut analysis = false;
+    let mut csv_path = None;
+    let mut query = None;
+    for arg in std::env::args().skip(1) {
+        match arg.split_once("=") {
+            Some(("rules", path)) => {
+                spec = fs::read_to_string(path)
+                    .map_err(|e| eprintln!("ERROR: Failed to read rules from {path}: {e}"))
+                    .unwrap()
+            }
+            Some(("counts", "auto")) => mode = CountMode::Auto,
+            Some(("counts", "u64")) => mode = CountMode::U64,
+            Some(("counts", "u128")) => mode = CountMode::U128,
+            Some(("counts", "big")) => mode = CountMode::Big,
+            Some(("blinks", n)) => {
+                blinks = vec![n.parse().expect("Blinks should be a positive integer")]
+            }
+            Some(("csv", path)) => csv_path = Some(path.to_owned()),
+            Some(("stone", s)) => {
+                query = Some(s.parse::<u128>().expect("Stone should be an integer"))
+            }
+            None if arg == "analyse" => analysis = true,
+            _ => {
+                eprintln!("ERROR: Unknown option {arg}.");
+                return;
+            }
+        }
+    }
+    let rules = parse_rules(&spec).unwrap();
+    let n_blinks = *blinks.iter().max().expect("at least one blink count");
+
+    // Analysis counts with arbitrary precision, deep blinks overflow anything smaller.
+    if let Some(s) = query {
+        let curve = match stone_curve::<BigCount>(&rules, s, n_blinks) {
+            Ok(curve) => curve,
+            Err(e) => return report_overflow(e),
+        };
+        for &n in &blinks {
+            println!("Stone {s} after {n} blinks: {}", curve[n as usize]);
+        }
+        if let Some(path) = csv_path {
+            fs::write(&path, growth_csv(&curve, None))
+                .map_err(|e| eprintln!("ERROR: Failed to write {path}: {e}"))
+                .unwrap();
+        }
+        return;
+    }
+    if analysis || csv_path.is_some() {
+        let result = match analyse::<BigCount>(&rules, &stones, n_blinks) {
+            Ok(result) => result,
+            Err(e) => return report_overflow(e),
+        };
+        if analysis {
+            for (blink, distinct) in result.distinct.iter().enumerate() {
+                println!("Blink {blink}: {distinct} distinct values");
+            }
+            match result.closed_at {
+                Some(blink) => println!(
+                    "Value set closes at blink {blink} with {seen} values.",
+                    seen = result.values_seen
+                ),
+                None => println!("Value set does not close within {n_blinks} blinks."),
+            }
+        }
+        if let Some(path) = csv_path {
+            fs::write(&path, growth_csv(&result.stones, Some(&result.distinct)))
+                .map_err(|e| eprintln!("ERROR: Failed to write {path}: {e}"))
+                .unwrap();
+        }
+        return;
+    }
+
+    let totals = match count_stones_with(mode, &rules, &stones, &blinks) {
+        Ok(totals) => totals,
+        Err(e) => return report_overflow(e),
+    };
+    if let [fst, snd] = totals.as_slice() {
+        println!("Day 11, part 1: {fst}");
+        println!("Day 11, part 2: {snd}");
+    } else {
+        for (n, total) in blinks.iter().zip(totals) {
+            println!("Day 11, {n} blinks: {total}");
+        }
+    }
 }
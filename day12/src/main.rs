@@ -49,12 +49,13 @@ fn visit_region(
     visited: &mut [bool],
     area: &mut u32,
     perimeter: &mut u32,
-    boundaries: &mut Vec<(f32, f32)>,
+    sides: &mut u32,
 ) {
     if visited[start] {
         return;
     }
     *area += 1;
+    *sides += count_corners(map, start);
     let y = start / map.width;
     let x = start % map.width;
     visited[start] = true;
@@ -65,43 +66,37 @@ fn visit_region(
             let nc = ny as usize * map.width + nx as usize;
             if map[nc] == map[start] {
                 if !visited[nc] {
-                    visit_region(map, nc, visited, area, perimeter, boundaries);
+                    visit_region(map, nc, visited, area, perimeter, sides);
                 }
             } else {
                 *perimeter += 1;
-                boundaries.push((
-                    x as f32 + (dir_x as f32) / 4.0,
-                    y as f32 + (dir_y as f32) / 4.0,
-                ))
             }
         } else {
             *perimeter += 1;
-            boundaries.push((
-                x as f32 + (dir_x as f32) / 4.0,
-                y as f32 + (dir_y as f32) / 4.0,
-            ))
         }
     }
 }
 
-fn count_sides(boundaries: &[(f32, f32)]) -> u32 {
-    let mut vertical = 1;
-    let mut boundaries: Vec<_> = boundaries
-        .iter()
-        .filter(|(bx, _)| bx.fract().abs() > 0.1)
-        .collect();
-    boundaries.sort_by(|a, b| a.partial_cmp(b).unwrap());
+fn same_plant(map: &CharArray, cell: usize, x: i32, y: i32) -> bool {
+    map.is_valid(x, y) && map[y as usize * map.width + x as usize] == map[cell]
+}
 
-    let (mut start_x, mut cur_y) = boundaries[0];
-    for &(bx, by) in boundaries[1..].iter() {
-        if *bx != start_x || *by > cur_y + 1.0 {
-            vertical += 1;
+// A region has as many sides as corners, holes included. A cell corner is convex when
+// neither edge neighbour around it is in the region, and concave when both are but the
+// diagonal is not. Regions touching only diagonally each get their own convex corner.
+fn count_corners(map: &CharArray, cell: usize) -> u32 {
+    let x = (cell % map.width) as i32;
+    let y = (cell / map.width) as i32;
+    let mut corners = 0;
+    for (dir_y, dir_x) in [(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+        let side_x = same_plant(map, cell, x + dir_x, y);
+        let side_y = same_plant(map, cell, x, y + dir_y);
+        let diagonal = same_plant(map, cell, x + dir_x, y + dir_y);
+        if (!side_x && !side_y) || (side_x && side_y && !diagonal) {
+            corners += 1;
         }
-        cur_y = *by;
-        start_x = *bx;
     }
-    // for every vertical side there needs to be a horizontal one
-    2 * vertical
+    corners
 }
 
 fn map_price(map: &CharArray) -> (u32, u32) {
@@ -114,17 +109,10 @@ fn map_price(map: &CharArray) -> (u32, u32) {
         }
         let mut area = 0;
         let mut peri = 0;
-        let mut boundaries = vec![];
-        visit_region(
-            map,
-            cur,
-            &mut visited,
-            &mut area,
-            &mut peri,
-            &mut boundaries,
-        );
+        let mut sides = 0;
+        visit_region(map, cur, &mut visited, &mut area, &mut peri, &mut sides);
         price_peri += area * peri;
-        price_sides += area * count_sides(&boundaries);
+        price_sides += area * sides;
     }
     (price_peri, price_sides)
 }